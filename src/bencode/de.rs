@@ -0,0 +1,288 @@
+// serde deserializer reading bencode, decodes to an Item tree then walks it
+#![allow(dead_code)]
+
+use super::{decode::parse, error::Error, Dict, Item};
+
+use std::borrow::Cow;
+
+use serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, Visitor},
+    forward_to_deserialize_any, Deserialize,
+};
+
+// deserializes a value from bencoded bytes, strings may borrow from bytes
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T, Error> {
+    from_item(parse(bytes)?)
+}
+
+// deserializes a value from an already decoded Item tree
+pub fn from_item<'de, T: Deserialize<'de>>(item: Item<'de>) -> Result<T, Error> {
+    T::deserialize(Deserializer { item })
+}
+
+fn type_name(item: &Item) -> &'static str {
+    match item {
+        Item::Int(_) => "integer",
+        Item::String(_) => "byte string",
+        Item::List(_) => "list",
+        Item::Dict(_) => "dict",
+    }
+}
+
+fn visit_bytes<'de, V: Visitor<'de>>(str: Cow<'de, [u8]>, visitor: V) -> Result<V::Value, Error> {
+    match str {
+        Cow::Borrowed(b) => visitor.visit_borrowed_bytes(b),
+        Cow::Owned(b) => visitor.visit_byte_buf(b),
+    }
+}
+
+// strings are only handed out as str when they are valid utf8
+fn visit_str<'de, V: Visitor<'de>>(str: Cow<'de, [u8]>, visitor: V) -> Result<V::Value, Error> {
+    match str {
+        Cow::Borrowed(b) => match std::str::from_utf8(b) {
+            Ok(s) => visitor.visit_borrowed_str(s),
+            Err(_) => visitor.visit_borrowed_bytes(b),
+        },
+        Cow::Owned(b) => match String::from_utf8(b) {
+            Ok(s) => visitor.visit_string(s),
+            Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+        },
+    }
+}
+
+pub struct Deserializer<'de> {
+    item: Item<'de>,
+}
+
+impl<'de> Deserializer<'de> {
+    fn invalid<T>(&self, exp: &str) -> Result<T, Error> {
+        Err(de::Error::custom(format!(
+            "expected {}, found {}",
+            exp,
+            type_name(&self.item)
+        )))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.item {
+            Item::Int(int) => visitor.visit_i64(int),
+            Item::String(str) => visit_bytes(str, visitor),
+            Item::List(list) => visitor.visit_seq(SeqAccess {
+                iter: list.into_iter(),
+            }),
+            Item::Dict(dict) => visitor.visit_map(MapAccess::new(dict)),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.item {
+            Item::Int(int) => visitor.visit_bool(int != 0),
+            _ => self.invalid("integer"),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.item {
+            Item::String(str) => visit_str(str, visitor),
+            _ => self.invalid("byte string"),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    // any value present in the dict is Some, missing fields become None
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.item {
+            Item::String(_) => visitor.visit_enum(EnumAccess {
+                variant: self.item,
+                value: None,
+            }),
+            Item::Dict(dict) if dict.len() == 1 => {
+                let (k, v) = dict.into_iter().next().unwrap();
+                visitor.visit_enum(EnumAccess {
+                    variant: Item::String(k),
+                    value: Some(v),
+                })
+            }
+            _ => self.invalid("byte string or single entry dict"),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char
+        bytes byte_buf seq tuple tuple_struct map struct
+    }
+}
+
+struct SeqAccess<'de> {
+    iter: std::vec::IntoIter<Item<'de>>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some(item) => seed.deserialize(Deserializer { item }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapAccess<'de> {
+    iter: std::collections::btree_map::IntoIter<Cow<'de, [u8]>, Item<'de>>,
+    value: Option<Item<'de>>,
+}
+
+impl<'de> MapAccess<'de> {
+    fn new(dict: Dict<'de>) -> Self {
+        Self {
+            iter: dict.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((k, v)) => {
+                self.value = Some(v);
+                seed.deserialize(Deserializer {
+                    item: Item::String(k),
+                })
+                .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(item) => seed.deserialize(Deserializer { item }),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess<'de> {
+    variant: Item<'de>,
+    value: Option<Item<'de>>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = VariantAccess<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(Deserializer { item: self.variant })?;
+        Ok((variant, VariantAccess { value: self.value }))
+    }
+}
+
+struct VariantAccess<'de> {
+    value: Option<Item<'de>>,
+}
+
+impl<'de> VariantAccess<'de> {
+    fn value(self) -> Result<Deserializer<'de>, Error> {
+        match self.value {
+            Some(item) => Ok(Deserializer { item }),
+            None => Err(de::Error::custom("expected variant with a value")),
+        }
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self.value()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self.value()?, visitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Item<'de> {
+    type Deserializer = Deserializer<'de>;
+
+    fn into_deserializer(self) -> Deserializer<'de> {
+        Deserializer { item: self }
+    }
+}
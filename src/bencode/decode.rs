@@ -1,94 +1,366 @@
 // functionality for decoding bencoded byte strings
 #![allow(dead_code)]
 
-use super::Item;
+use super::{Dict, Item};
 
-use std::collections::BTreeMap;
+use std::{borrow::Cow, fmt, ops::Range};
 
-fn parse_int(str: &mut Vec<u8>) -> usize {
-    let mut len: usize = 0;
-    let mut int_string: String = String::new();
-    for c in str.iter() {
-        len += 1;
-        if *c == b'i' {
-            continue;
+// deepest nesting of lists and dicts accepted before bailing out
+pub const MAX_DEPTH: usize = 64;
+
+// kinds of failure when decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Truncated,
+    BadLength,
+    BadInt,
+    IntOverflow,
+    LeadingZero,
+    Unterminated,
+    TrailingData,
+    TooDeep,
+    UnexpectedByte(u8),
+}
+
+// failure kind along with the byte offset into the input it occured at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self.kind {
+            ErrorKind::Truncated => "input ends early".to_string(),
+            ErrorKind::BadLength => "invalid string length prefix".to_string(),
+            ErrorKind::BadInt => "invalid integer".to_string(),
+            ErrorKind::IntOverflow => "integer does not fit in 64 bits".to_string(),
+            ErrorKind::LeadingZero => "leading zero in number".to_string(),
+            ErrorKind::Unterminated => "list or dict not terminated".to_string(),
+            ErrorKind::TrailingData => "trailing data after item".to_string(),
+            ErrorKind::TooDeep => format!("nested deeper than {}", MAX_DEPTH),
+            ErrorKind::UnexpectedByte(b) => format!("unexpected byte {:#04x}", b),
+        };
+        write!(f, "bencode: {} at offset {}", msg, self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// cursor over a borrowed buffer, items borrow their strings from it
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            depth: 0,
         }
-        if *c == b'e' {
-            break;
+    }
+
+    // offset of the next unread byte
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    // true when every byte has been consumed
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn error(&self, kind: ErrorKind) -> DecodeError {
+        DecodeError {
+            offset: self.pos,
+            kind,
         }
-        int_string.push(*c as char);
     }
-    str.drain(0..len);
-    int_string.parse::<usize>().unwrap()
-}
 
-fn parse_str(str: &mut Vec<u8>) -> Vec<u8> {
-    let mut int_len: usize = 0;
-    let mut int_string: String = String::new();
-    for c in str.iter() {
-        int_len += 1;
-        if *c == b':' {
-            break;
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    // returns the bytes up to the terminator, consuming both
+    fn take_until(&mut self, term: u8) -> Result<&'a [u8], DecodeError> {
+        let rest = &self.buf[self.pos..];
+        let len = match rest.iter().position(|c| *c == term) {
+            Some(l) => l,
+            None => {
+                return Err(DecodeError {
+                    offset: self.buf.len(),
+                    kind: ErrorKind::Truncated,
+                })
+            }
+        };
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+
+    // checks digits are non-empty, all ascii digits and have no leading zeros
+    fn check_digits(&self, digits: &[u8], at: usize, bad: ErrorKind) -> Result<(), DecodeError> {
+        if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_digit()) {
+            return Err(DecodeError {
+                offset: at,
+                kind: bad,
+            });
         }
-        int_string.push(*c as char);
+        if digits.len() > 1 && digits[0] == b'0' {
+            return Err(DecodeError {
+                offset: at,
+                kind: ErrorKind::LeadingZero,
+            });
+        }
+        Ok(())
     }
-    let len: usize = int_string.parse::<usize>().unwrap();
-    str.drain(0..int_len);
 
-    let s = str[..len].to_vec();
-    let mut copy = str[len..].to_vec();
-    str.clear();
-    str.append(&mut copy);
-    s
-}
+    // parses i<digits>e where digits is canonical, i.e. no -0 or leading zeros
+    fn parse_int(&mut self) -> Result<i64, DecodeError> {
+        self.pos += 1;
+        let at = self.pos;
+        let int = self.take_until(b'e')?;
+        let digits = int.strip_prefix(b"-").unwrap_or(int);
+        self.check_digits(digits, at, ErrorKind::BadInt)?;
+        if digits != int && digits == b"0" {
+            return Err(DecodeError {
+                offset: at,
+                kind: ErrorKind::BadInt,
+            });
+        }
+        // digits are all ascii so this is valid utf8
+        std::str::from_utf8(int)
+            .unwrap()
+            .parse::<i64>()
+            .map_err(|_| DecodeError {
+                offset: at,
+                kind: ErrorKind::IntOverflow,
+            })
+    }
 
-fn parse_list(str: &mut Vec<u8>) -> Vec<Item> {
-    str.drain(0..1);
-    let mut list: Vec<Item> = Vec::<Item>::new();
-    loop {
-        match *str.get(0).unwrap() as char {
-            'i' => list.push(Item::Int(parse_int(str))),
-            'l' => list.push(Item::List(parse_list(str))),
-            'd' => list.push(Item::Dict(parse_dict(str))),
-            '0'..='9' => list.push(Item::String(parse_str(str))),
-            'e' => break,
-            _ => unreachable!(),
+    fn parse_str(&mut self) -> Result<&'a [u8], DecodeError> {
+        let at = self.pos;
+        let digits = self.take_until(b':')?;
+        self.check_digits(digits, at, ErrorKind::BadLength)?;
+        // digits are all ascii so this is valid utf8
+        let len = std::str::from_utf8(digits)
+            .unwrap()
+            .parse::<usize>()
+            .map_err(|_| DecodeError {
+                offset: at,
+                kind: ErrorKind::BadLength,
+            })?;
+        if self.buf.len() - self.pos < len {
+            return Err(DecodeError {
+                offset: self.buf.len(),
+                kind: ErrorKind::Truncated,
+            });
         }
+
+        let s = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(s)
     }
-    str.drain(0..1);
-    list
-}
 
-fn parse_dict(str: &mut Vec<u8>) -> BTreeMap<Vec<u8>, Item> {
-    str.drain(0..1);
-    let mut dict: BTreeMap<Vec<u8>, Item> = BTreeMap::new();
-    loop {
-        if *str.get(0).unwrap() == b'e' {
-            break;
+    fn parse_list(&mut self) -> Result<Vec<Item<'a>>, DecodeError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut list: Vec<Item> = Vec::<Item>::new();
+        loop {
+            match self.peek() {
+                Some(b'e') => break,
+                Some(_) => list.push(self.parse_item()?),
+                None => {
+                    return Err(DecodeError {
+                        offset: start,
+                        kind: ErrorKind::Unterminated,
+                    })
+                }
+            }
         }
-        let s = parse_str(str);
-        match *str.get(0).unwrap() as char {
-            'i' => dict.insert(s, Item::Int(parse_int(str))),
-            'l' => dict.insert(s, Item::List(parse_list(str))),
-            'd' => dict.insert(s, Item::Dict(parse_dict(str))),
-            '0'..='9' => dict.insert(s, Item::String(parse_str(str))),
-            _ => unreachable!(),
-        };
+        self.pos += 1;
+        Ok(list)
+    }
+
+    fn parse_dict(&mut self) -> Result<Dict<'a>, DecodeError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut dict: Dict = Dict::new();
+        loop {
+            match self.peek() {
+                Some(b'e') => break,
+                Some(b'0'..=b'9') => {
+                    let s = self.parse_str()?;
+                    let val = self.parse_item()?;
+                    dict.insert(Cow::Borrowed(s), val);
+                }
+                Some(c) => return Err(self.error(ErrorKind::UnexpectedByte(c))),
+                None => {
+                    return Err(DecodeError {
+                        offset: start,
+                        kind: ErrorKind::Unterminated,
+                    })
+                }
+            }
+        }
+        self.pos += 1;
+        Ok(dict)
+    }
+
+    // guards list and dict recursion against the depth limit
+    fn parse_nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<T, DecodeError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(ErrorKind::TooDeep));
+        }
+        self.depth += 1;
+        let ret = f(self);
+        self.depth -= 1;
+        ret
+    }
+
+    // walks the dict at the cursor down the key path, returning the source range of the value
+    fn find_span(&mut self, path: &[&[u8]]) -> Result<Option<Range<usize>>, DecodeError> {
+        if self.peek() != Some(b'd') {
+            self.parse_item()?;
+            return Ok(None);
+        }
+        let start = self.pos;
+        self.pos += 1;
+        let mut span = None;
+        loop {
+            match self.peek() {
+                Some(b'e') => break,
+                Some(b'0'..=b'9') => {
+                    let key = self.parse_str()?;
+                    if span.is_some() || path.is_empty() || key != path[0] {
+                        self.parse_item()?;
+                    } else if path.len() == 1 {
+                        let begin = self.pos;
+                        self.parse_item()?;
+                        span = Some(begin..self.pos);
+                    } else {
+                        span = self.parse_nested(|d| d.find_span(&path[1..]))?;
+                    }
+                }
+                Some(c) => return Err(self.error(ErrorKind::UnexpectedByte(c))),
+                None => {
+                    return Err(DecodeError {
+                        offset: start,
+                        kind: ErrorKind::Unterminated,
+                    })
+                }
+            }
+        }
+        self.pos += 1;
+        Ok(span)
     }
-    str.drain(0..1);
-    dict
-}
 
-pub fn parse(str: &mut Vec<u8>) -> Vec<Item> {
-    let mut tree: Vec<Item> = Vec::<Item>::new();
-    while let Some(c) = str.get(0) {
-        match *c {
-            b'i' => tree.push(Item::Int(parse_int(str))),
-            b'l' => tree.push(Item::List(parse_list(str))),
-            b'd' => tree.push(Item::Dict(parse_dict(str))),
-            b'0'..=b'9' => tree.push(Item::String(parse_str(str))),
-            _ => break,
+    // parses the next item at the cursor
+    pub fn parse_item(&mut self) -> Result<Item<'a>, DecodeError> {
+        match self.peek() {
+            Some(b'i') => Ok(Item::Int(self.parse_int()?)),
+            Some(b'l') => Ok(Item::List(self.parse_nested(Self::parse_list)?)),
+            Some(b'd') => Ok(Item::Dict(self.parse_nested(Self::parse_dict)?)),
+            Some(b'0'..=b'9') => Ok(Item::String(Cow::Borrowed(self.parse_str()?))),
+            Some(c) => Err(self.error(ErrorKind::UnexpectedByte(c))),
+            None => Err(self.error(ErrorKind::Truncated)),
         }
     }
-    tree
+}
+
+// parses exactly one bencoded item, erroring on malformed or trailing input
+pub fn parse(buf: &[u8]) -> Result<Item<'_>, DecodeError> {
+    let mut decoder = Decoder::new(buf);
+    let item = decoder.parse_item()?;
+    if !decoder.is_empty() {
+        return Err(decoder.error(ErrorKind::TrailingData));
+    }
+    Ok(item)
+}
+
+// byte range in buf of the value reached by following the dict keys in path,
+// used to hash dicts exactly as they were encoded
+pub fn find_span(buf: &[u8], path: &[&[u8]]) -> Result<Option<Range<usize>>, DecodeError> {
+    let mut decoder = Decoder::new(buf);
+    let span = decoder.parse_nested(|d| d.find_span(path))?;
+    if !decoder.is_empty() {
+        return Err(decoder.error(ErrorKind::TrailingData));
+    }
+    Ok(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err(buf: &[u8]) -> (ErrorKind, usize) {
+        let e = parse(buf).unwrap_err();
+        (e.kind, e.offset)
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(err(b""), (ErrorKind::Truncated, 0));
+        assert_eq!(err(b"i12"), (ErrorKind::Truncated, 3));
+        assert_eq!(err(b"5:abc"), (ErrorKind::Truncated, 5));
+        assert_eq!(err(b"12"), (ErrorKind::Truncated, 2));
+        assert_eq!(err(b"d1:a"), (ErrorKind::Truncated, 4));
+        assert_eq!(err(b"li1e"), (ErrorKind::Unterminated, 0));
+        assert_eq!(err(b"d1:ali1ee"), (ErrorKind::Unterminated, 0));
+    }
+
+    #[test]
+    fn garbage_input() {
+        assert_eq!(err(b"x"), (ErrorKind::UnexpectedByte(b'x'), 0));
+        assert_eq!(err(b"li1ex"), (ErrorKind::UnexpectedByte(b'x'), 4));
+        // dict keys have to be strings
+        assert_eq!(err(b"di1ei2ee"), (ErrorKind::UnexpectedByte(b'i'), 1));
+        assert_eq!(err(b"i1ei2e"), (ErrorKind::TrailingData, 3));
+        assert_eq!(err(b"1x:a"), (ErrorKind::BadLength, 0));
+        assert_eq!(err(b"03:abc"), (ErrorKind::LeadingZero, 0));
+
+        let deep = [vec![b'l'; MAX_DEPTH + 1], vec![b'e'; MAX_DEPTH + 1]].concat();
+        assert_eq!(err(&deep), (ErrorKind::TooDeep, MAX_DEPTH));
+        let ok = [vec![b'l'; MAX_DEPTH], vec![b'e'; MAX_DEPTH]].concat();
+        assert!(parse(&ok).is_ok());
+    }
+
+    #[test]
+    fn non_canonical_ints() {
+        assert_eq!(err(b"i-0e"), (ErrorKind::BadInt, 1));
+        assert_eq!(err(b"i03e"), (ErrorKind::LeadingZero, 1));
+        assert_eq!(err(b"i-03e"), (ErrorKind::LeadingZero, 1));
+        assert_eq!(err(b"ie"), (ErrorKind::BadInt, 1));
+        assert_eq!(err(b"i-e"), (ErrorKind::BadInt, 1));
+        assert_eq!(err(b"i1.5e"), (ErrorKind::BadInt, 1));
+        assert_eq!(err(b"i9223372036854775808e"), (ErrorKind::IntOverflow, 1));
+        assert_eq!(err(b"i-9223372036854775809e"), (ErrorKind::IntOverflow, 1));
+
+        assert_eq!(parse(b"i0e").unwrap().get_int(), Some(0));
+        assert_eq!(parse(b"i-42e").unwrap().get_int(), Some(-42));
+        assert_eq!(
+            parse(b"i9223372036854775807e").unwrap().get_int(),
+            Some(i64::MAX)
+        );
+        assert_eq!(
+            parse(b"i-9223372036854775808e").unwrap().get_int(),
+            Some(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn span_of_nested_value() {
+        let buf = b"d4:infod6:lengthi5ee3:zzzi1ee";
+        let span = find_span(buf, &[b"info"]).unwrap().unwrap();
+        assert_eq!(&buf[span], b"d6:lengthi5ee");
+        let span = find_span(buf, &[b"info", b"length"]).unwrap().unwrap();
+        assert_eq!(&buf[span], b"i5e");
+        assert_eq!(find_span(buf, &[b"missing"]).unwrap(), None);
+    }
 }
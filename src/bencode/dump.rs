@@ -0,0 +1,142 @@
+// human readable and json renderings of bencode trees for inspection
+#![allow(dead_code)]
+
+use super::Item;
+
+use std::fmt::Write;
+
+// binary strings longer than this are cut short in pretty output
+const HEX_PREVIEW: usize = 32;
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+// quotes and escapes a string for json
+fn quote(str: &str, out: &mut String) {
+    out.push('"');
+    for c in str.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn pretty_str(key: Option<&[u8]>, str: &[u8], out: &mut String) {
+    if key == Some(b"pieces") && str.len().is_multiple_of(20) {
+        write!(out, "<{} piece hashes>", str.len() / 20).unwrap();
+        return;
+    }
+    match std::str::from_utf8(str) {
+        Ok(s) => quote(s, out),
+        Err(_) if str.len() > HEX_PREVIEW => {
+            write!(out, "<{}... {} bytes>", hex(&str[..HEX_PREVIEW]), str.len()).unwrap()
+        }
+        Err(_) => write!(out, "<{}>", hex(str)).unwrap(),
+    }
+}
+
+fn pretty_item(key: Option<&[u8]>, item: &Item, depth: usize, out: &mut String) {
+    match item {
+        Item::Int(int) => write!(out, "{}", int).unwrap(),
+        Item::String(str) => pretty_str(key, str, out),
+        Item::List(list) if list.is_empty() => out.push_str("[]"),
+        Item::List(list) => {
+            out.push_str("[\n");
+            for (i, item) in list.iter().enumerate() {
+                indent(out, depth + 1);
+                pretty_item(None, item, depth + 1, out);
+                if i + 1 < list.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            indent(out, depth);
+            out.push(']');
+        }
+        Item::Dict(dict) if dict.is_empty() => out.push_str("{}"),
+        Item::Dict(dict) => {
+            out.push_str("{\n");
+            for (i, (k, v)) in dict.iter().enumerate() {
+                indent(out, depth + 1);
+                pretty_str(None, k, out);
+                out.push_str(": ");
+                pretty_item(Some(k), v, depth + 1, out);
+                if i + 1 < dict.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+    }
+}
+
+// indented json-like text, binary strings as hex and piece hashes summarized
+pub fn pretty(item: &Item) -> String {
+    let mut out = String::new();
+    pretty_item(None, item, 0, &mut out);
+    out
+}
+
+// strings that aren't utf8 are emitted in full as hex
+fn json_str(str: &[u8], out: &mut String) {
+    match std::str::from_utf8(str) {
+        Ok(s) => quote(s, out),
+        Err(_) => quote(&hex(str), out),
+    }
+}
+
+fn json_item(item: &Item, out: &mut String) {
+    match item {
+        Item::Int(int) => write!(out, "{}", int).unwrap(),
+        Item::String(str) => json_str(str, out),
+        Item::List(list) => {
+            out.push('[');
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json_item(item, out);
+            }
+            out.push(']');
+        }
+        Item::Dict(dict) => {
+            out.push('{');
+            for (i, (k, v)) in dict.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json_str(k, out);
+                out.push(':');
+                json_item(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+// compact, valid json for piping into other tools
+pub fn json(item: &Item) -> String {
+    let mut out = String::new();
+    json_item(item, &mut out);
+    out
+}
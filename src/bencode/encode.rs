@@ -1,58 +1,95 @@
 // functionality for encoding bencode trees
 #![allow(dead_code)]
 
-use super::Item;
+use super::{Dict, Item};
 
-use std::{collections::BTreeMap, str::from_utf8};
+use std::io::{self, Write};
 
-fn encode_int(int: usize) -> Vec<u8> {
-    format!("i{}e", int).as_bytes().to_vec()
+// i64 formatting never emits leading zeros or -0, so this is always canonical
+fn encode_int<W: Write>(int: i64, w: &mut W) -> io::Result<()> {
+    write!(w, "i{}e", int)
 }
 
-fn encode_str(str: &[u8]) -> Vec<u8> {
-    format!("{}:{}", str.len(), from_utf8(&str).unwrap())
-        .as_bytes()
-        .to_vec()
+// strings are written verbatim so binary data such as piece hashes survives
+fn encode_str<W: Write>(str: &[u8], w: &mut W) -> io::Result<()> {
+    write!(w, "{}:", str.len())?;
+    w.write_all(str)
 }
 
-fn encode_dict(dict: BTreeMap<Vec<u8>, Item>) -> Vec<u8> {
-    let mut encdict: Vec<u8> = vec![b'd'];
+// keys come out of the BTreeMap sorted, as bencode requires
+fn encode_dict<W: Write>(dict: &Dict, w: &mut W) -> io::Result<()> {
+    w.write_all(b"d")?;
     for (key, val) in dict {
-        encdict.extend_from_slice(&encode_str(&key));
-        match val {
-            Item::Int(int) => encdict.extend_from_slice(&encode_int(int)),
-            Item::String(str) => encdict.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => encdict.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => encdict.extend_from_slice(&encode_dict(dict)),
-        }
+        encode_str(key, w)?;
+        encode_to(val, w)?;
     }
-    encdict.push(b'e');
-    encdict
+    w.write_all(b"e")
 }
 
-fn encode_list(list: Vec<Item>) -> Vec<u8> {
-    let mut enclist: Vec<u8> = vec![b'l'];
+fn encode_list<W: Write>(list: &[Item], w: &mut W) -> io::Result<()> {
+    w.write_all(b"l")?;
     for item in list {
-        match item {
-            Item::Int(int) => enclist.extend_from_slice(&encode_int(int)),
-            Item::String(str) => enclist.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => enclist.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => enclist.extend_from_slice(&encode_dict(dict)),
-        }
+        encode_to(item, w)?;
     }
-    enclist.push(b'e');
-    enclist
+    w.write_all(b"e")
 }
 
-pub fn encode(tree: Vec<Item>) -> Vec<u8> {
-    let mut enctree: Vec<u8> = vec![];
-    for item in tree {
-        match item {
-            Item::Int(int) => enctree.extend_from_slice(&encode_int(int)),
-            Item::String(str) => enctree.extend_from_slice(&encode_str(&str)),
-            Item::List(list) => enctree.extend_from_slice(&encode_list(list)),
-            Item::Dict(dict) => enctree.extend_from_slice(&encode_dict(dict)),
-        }
+// writes the bencoding of item, for canonical input encode(parse(x)) == x
+pub fn encode_to<W: Write>(item: &Item, w: &mut W) -> io::Result<()> {
+    match item {
+        Item::Int(int) => encode_int(*int, w),
+        Item::String(str) => encode_str(str, w),
+        Item::List(list) => encode_list(list, w),
+        Item::Dict(dict) => encode_dict(dict, w),
+    }
+}
+
+pub fn encode(item: &Item) -> Vec<u8> {
+    let mut buf = vec![];
+    // writing to a vec can't fail
+    encode_to(item, &mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode::parse;
+
+    use std::borrow::Cow;
+
+    #[test]
+    fn non_utf8_round_trips() {
+        let binary = vec![0xff, 0x00, 0xfe, b':', b'e', 0x80];
+        let mut dict = Dict::new();
+        dict.insert(Cow::Owned(vec![0xc3, 0x28]), Item::Int(-7));
+        dict.insert(
+            Cow::Borrowed(&b"pieces"[..]),
+            Item::String(Cow::Owned(binary.clone())),
+        );
+        let item = Item::List(vec![Item::Dict(dict), Item::String(Cow::Owned(vec![]))]);
+
+        let bytes = encode(&item);
+        // keys sort as raw bytes, 0xc3 after ascii
+        let expected = [
+            &b"ld6:pieces6:"[..],
+            &binary,
+            b"2:",
+            &[0xc3, 0x28],
+            b"i-7ee0:e",
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+
+        let decoded = parse(&bytes).unwrap();
+        let dict = &decoded.get_list().unwrap()[0];
+        assert_eq!(dict["pieces"].get_str(), Some(&binary[..]));
+        assert_eq!(encode(&decoded), bytes);
+    }
+
+    #[test]
+    fn canonical_input_encodes_back_identically() {
+        let input = b"d8:announce3:url4:infod6:lengthi1e4:name1:a6:pieces2:\x01\xffee";
+        assert_eq!(encode(&parse(input).unwrap()), input);
     }
-    enctree
 }
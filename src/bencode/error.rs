@@ -0,0 +1,41 @@
+// error type shared by the serde serializer and deserializer
+#![allow(dead_code)]
+
+use super::decode::DecodeError;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Decode(DecodeError),
+    Message(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Decode(e) => write!(f, "{}", e),
+            Error::Message(m) => write!(f, "bencode: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
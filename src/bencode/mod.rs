@@ -1,50 +1,244 @@
 // bencode subfolder and item enum implemenation
 #![allow(dead_code)]
 
+pub mod de;
 pub mod decode;
+pub mod dump;
 pub mod encode;
+pub mod error;
+pub mod ser;
+pub mod stream;
 
-use std::collections::BTreeMap;
+#[allow(unused_imports)]
+pub use self::{de::from_bytes, error::Error, ser::to_bytes};
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    ops::{Deref, Index},
+};
+
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+// strings borrow from the decoded buffer where possible
+pub type Dict<'a> = BTreeMap<Cow<'a, [u8]>, Item<'a>>;
 
 #[derive(Clone, Debug)]
-pub enum Item {
-    Int(usize),
-    String(Vec<u8>),
-    List(Vec<Item>),
-    Dict(BTreeMap<Vec<u8>, Item>),
-}
-
-impl Item {
-    #[allow(dead_code)]
-    pub fn get_int(&self) -> usize {
-        let int = match &self {
-            Item::Int(int) => int,
-            _ => unreachable!(),
-        };
-        *int
-    }
-    #[allow(dead_code)]
-    pub fn get_str(&self) -> Vec<u8> {
-        let str = match &self {
-            Item::String(str) => str,
-            _ => unreachable!(),
-        };
-        str.clone()
-    }
-    #[allow(dead_code)]
-    pub fn get_list(&self) -> Vec<Item> {
-        let list = match &self {
-            Item::List(list) => list,
-            _ => unreachable!(),
-        };
-        list.clone()
-    }
-    #[allow(dead_code)]
-    pub fn get_dict(&self) -> BTreeMap<Vec<u8>, Item> {
-        let dict = match &self {
-            Item::Dict(dict) => dict,
-            _ => unreachable!(),
-        };
-        dict.clone()
+pub enum Item<'a> {
+    Int(i64),
+    String(Cow<'a, [u8]>),
+    List(Vec<Item<'a>>),
+    Dict(Dict<'a>),
+}
+
+impl<'a> Item<'a> {
+    // typed accessors borrow from the tree and return None on a type mismatch
+    pub fn get_int(&self) -> Option<i64> {
+        match self {
+            Item::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self) -> Option<&[u8]> {
+        match self {
+            Item::String(str) => Some(str),
+            _ => None,
+        }
+    }
+
+    pub fn get_list(&self) -> Option<&[Item<'a>]> {
+        match self {
+            Item::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn get_dict(&self) -> Option<&Dict<'a>> {
+        match self {
+            Item::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    // looks up a key if this is a dict
+    pub fn get<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> Option<&Item<'a>> {
+        self.get_dict()?.get(key.as_ref())
+    }
+
+    // follows a path of dict keys, e.g. get_path(&["info", "files"])
+    pub fn get_path<K: AsRef<[u8]>>(&self, path: &[K]) -> Option<&Item<'a>> {
+        let mut item = self;
+        for key in path {
+            item = item.get(key)?;
+        }
+        Some(item)
+    }
+
+    // copies any borrowed strings so the item outlives its buffer
+    pub fn into_owned(self) -> Item<'static> {
+        match self {
+            Item::Int(int) => Item::Int(int),
+            Item::String(str) => Item::String(Cow::Owned(str.into_owned())),
+            Item::List(list) => Item::List(list.into_iter().map(Item::into_owned).collect()),
+            Item::Dict(dict) => Item::Dict(
+                dict.into_iter()
+                    .map(|(k, v)| (Cow::Owned(k.into_owned()), v.into_owned()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// like BTreeMap, indexing panics when the key is missing or self isn't a dict
+impl<'a> Index<&[u8]> for Item<'a> {
+    type Output = Item<'a>;
+
+    fn index(&self, key: &[u8]) -> &Item<'a> {
+        match self.get(key) {
+            Some(item) => item,
+            None => panic!("no key {:?} in item", String::from_utf8_lossy(key)),
+        }
+    }
+}
+
+impl<'a> Index<&str> for Item<'a> {
+    type Output = Item<'a>;
+
+    fn index(&self, key: &str) -> &Item<'a> {
+        &self[key.as_bytes()]
+    }
+}
+
+// byte string newtype for serde structs, a plain Vec<u8> would become a list of ints
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteBuf(pub Vec<u8>);
+
+impl Deref for ByteBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for ByteBuf {
+    fn from(bytes: Vec<u8>) -> Self {
+        ByteBuf(bytes)
+    }
+}
+
+impl Serialize for ByteBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a byte string")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.into_bytes()))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct File {
+        length: i64,
+        path: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Info {
+        name: String,
+        #[serde(rename = "piece length")]
+        piece_length: u64,
+        pieces: ByteBuf,
+        private: Option<bool>,
+        comment: Option<String>,
+        files: Vec<File>,
+    }
+
+    fn info() -> Info {
+        Info {
+            name: "a".to_string(),
+            piece_length: 16384,
+            pieces: ByteBuf(vec![0xff, 0x00, 0x80]),
+            private: Some(true),
+            comment: None,
+            files: vec![File {
+                length: 3,
+                path: vec!["d".to_string(), "f".to_string()],
+            }],
+        }
+    }
+
+    #[test]
+    fn structs_map_to_sorted_dicts() {
+        let bytes = to_bytes(&info()).unwrap();
+        // keys sorted, None left out, bool as an int, ByteBuf verbatim
+        let expected = [
+            &b"d5:filesld6:lengthi3e4:pathl1:d1:feee4:name1:a12:piece lengthi16384e6:pieces3:"[..],
+            &[0xff, 0x00, 0x80],
+            b"7:privatei1ee",
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+        assert_eq!(from_bytes::<Info>(&bytes).unwrap(), info());
+    }
+
+    #[test]
+    fn missing_options_are_none_and_unknown_keys_ignored() {
+        let bytes = b"d7:unknowni1e5:filesle4:name1:b12:piece lengthi1e6:pieces0:e";
+        let info: Info = from_bytes(bytes).unwrap();
+        assert_eq!(info.private, None);
+        assert_eq!(info.comment, None);
+        assert_eq!(info.name, "b");
+        assert!(info.pieces.is_empty());
+    }
+
+    #[test]
+    fn type_mismatches_are_errors() {
+        // a non utf8 name can't be a String
+        assert!(from_bytes::<String>(b"2:\xc3\x28").is_err());
+        assert_eq!(
+            from_bytes::<ByteBuf>(b"2:\xc3\x28").unwrap().0,
+            [0xc3, 0x28]
+        );
+        assert!(from_bytes::<i64>(b"3:abc").is_err());
+        assert!(from_bytes::<Vec<i64>>(b"i1e").is_err());
+        assert!(matches!(
+            from_bytes::<i64>(b"i-0e"),
+            Err(Error::Decode(e)) if e.kind == decode::ErrorKind::BadInt
+        ));
     }
 }
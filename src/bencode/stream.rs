@@ -0,0 +1,204 @@
+// incremental decoding of bencoded items arriving in chunks, e.g. from a socket
+#![allow(dead_code)]
+
+use super::{
+    decode::{parse, DecodeError, ErrorKind, MAX_DEPTH},
+    Item,
+};
+
+pub enum Status {
+    NeedMore,
+    // decoded item and how many bytes of the stream it took up
+    Complete(Item<'static>, usize),
+}
+
+// where the scanner is within the current item
+#[derive(Clone, Copy)]
+enum State {
+    Value,
+    Int,
+    StrLen(usize),
+    Str(usize),
+}
+
+// buffers fed chunks and scans them once to find where the next item ends,
+// only then running the full decoder over it
+pub struct StreamDecoder {
+    buf: Vec<u8>,
+    scanned: usize,
+    depth: usize,
+    state: State,
+    // bytes handed out in completed items, keeps error offsets stream relative
+    base: usize,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self {
+            buf: vec![],
+            scanned: 0,
+            depth: 0,
+            state: State::Value,
+            base: 0,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    // bytes fed but not yet part of a completed item
+    pub fn remaining(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn error(&self, pos: usize, kind: ErrorKind) -> DecodeError {
+        DecodeError {
+            offset: self.base + pos,
+            kind,
+        }
+    }
+
+    // advances the scanner, returning the end of the item once it is complete
+    fn scan(&mut self) -> Result<Option<usize>, DecodeError> {
+        while self.scanned < self.buf.len() {
+            let pos = self.scanned;
+            let c = self.buf[pos];
+            self.scanned += 1;
+            let mut done = false;
+            match self.state {
+                State::Value => match c {
+                    b'i' => self.state = State::Int,
+                    b'l' | b'd' => {
+                        if self.depth >= MAX_DEPTH {
+                            return Err(self.error(pos, ErrorKind::TooDeep));
+                        }
+                        self.depth += 1;
+                    }
+                    b'0'..=b'9' => self.state = State::StrLen((c - b'0') as usize),
+                    b'e' if self.depth > 0 => {
+                        self.depth -= 1;
+                        done = true;
+                    }
+                    _ => return Err(self.error(pos, ErrorKind::UnexpectedByte(c))),
+                },
+                State::Int => match c {
+                    b'e' => {
+                        self.state = State::Value;
+                        done = true;
+                    }
+                    b'0'..=b'9' | b'-' => {}
+                    _ => return Err(self.error(pos, ErrorKind::BadInt)),
+                },
+                State::StrLen(len) => match c {
+                    b':' if len == 0 => {
+                        self.state = State::Value;
+                        done = true;
+                    }
+                    b':' => self.state = State::Str(len),
+                    b'0'..=b'9' => {
+                        let len = len
+                            .checked_mul(10)
+                            .and_then(|l| l.checked_add((c - b'0') as usize))
+                            .ok_or_else(|| self.error(pos, ErrorKind::BadLength))?;
+                        self.state = State::StrLen(len);
+                    }
+                    _ => return Err(self.error(pos, ErrorKind::BadLength)),
+                },
+                State::Str(len) => {
+                    // skip over the whole string body at once
+                    let avail = self.buf.len() - pos;
+                    if avail < len {
+                        self.scanned = self.buf.len();
+                        self.state = State::Str(len - avail);
+                    } else {
+                        self.scanned = pos + len;
+                        self.state = State::Value;
+                        done = true;
+                    }
+                }
+            }
+            if done && self.depth == 0 {
+                return Ok(Some(self.scanned));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // decodes the next item if all of it has arrived, leaving any bytes after it buffered
+    pub fn decode(&mut self) -> Result<Status, DecodeError> {
+        let end = match self.scan()? {
+            Some(end) => end,
+            None => return Ok(Status::NeedMore),
+        };
+        let item = parse(&self.buf[..end])
+            .map_err(|e| self.error(e.offset, e.kind))?
+            .into_owned();
+        self.buf.drain(..end);
+        self.scanned = 0;
+        self.base += end;
+
+        Ok(Status::Complete(item, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::encode::encode;
+
+    #[test]
+    fn items_fed_a_byte_at_a_time() {
+        let first = b"d8:intervali1800e5:peers6:\x7f\x00\x00\x01\x1a\xe1e";
+        let second = b"li-1e0:e";
+        let stream = [&first[..], second].concat();
+
+        let mut decoder = StreamDecoder::new();
+        let mut items = vec![];
+        for (i, byte) in stream.iter().enumerate() {
+            decoder.feed(&[*byte]);
+            match decoder.decode().unwrap() {
+                Status::NeedMore => {}
+                Status::Complete(item, len) => items.push((i, item, len)),
+            }
+        }
+
+        assert_eq!(items.len(), 2);
+        let (at, item, len) = &items[0];
+        assert_eq!((*at, *len), (first.len() - 1, first.len()));
+        assert_eq!(encode(item), first);
+        let (at, item, len) = &items[1];
+        assert_eq!((*at, *len), (stream.len() - 1, second.len()));
+        assert_eq!(encode(item), second);
+        assert!(decoder.remaining().is_empty());
+    }
+
+    #[test]
+    fn errors_are_offset_into_the_stream() {
+        let mut decoder = StreamDecoder::new();
+        decoder.feed(b"i1e");
+        assert!(matches!(decoder.decode().unwrap(), Status::Complete(_, 3)));
+        for byte in b"li0ex" {
+            decoder.feed(&[*byte]);
+            if let Err(e) = decoder.decode() {
+                assert_eq!(e.kind, ErrorKind::UnexpectedByte(b'x'));
+                assert_eq!(e.offset, 7);
+                return;
+            }
+        }
+        panic!("garbage accepted");
+    }
+
+    #[test]
+    fn non_canonical_ints_are_caught_once_complete() {
+        let mut decoder = StreamDecoder::new();
+        for byte in b"i03" {
+            decoder.feed(&[*byte]);
+            assert!(matches!(decoder.decode().unwrap(), Status::NeedMore));
+        }
+        decoder.feed(b"e");
+        let e = decoder.decode().err().unwrap();
+        assert_eq!((e.kind, e.offset), (ErrorKind::LeadingZero, 1));
+    }
+}
@@ -0,0 +1,324 @@
+// builds .torrent metainfo from a file or directory on disk
+#![allow(dead_code)]
+
+use crate::{
+    bencode::{self, ByteBuf},
+    hash::{spawn_hash_pieces, Hasher},
+    metainfo::{FileEntry, InfoDict, Metainfo, MetainfoError},
+    tcp_bt::msg::{structs::Piece, SUBPIECE_LEN},
+    tracker::get_info_hash,
+};
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// piece lengths stay within these bounds
+const MIN_PIECE_LEN: u64 = SUBPIECE_LEN as u64;
+const MAX_PIECE_LEN: u64 = 16 * 1024 * 1024;
+// piece count the automatic piece length aims to stay under
+const TARGET_PIECES: u64 = 1500;
+
+#[derive(Debug)]
+pub enum CreateError {
+    Io(io::Error),
+    NoFiles,
+    // path that isn't valid utf8 or has no file name
+    BadPath(PathBuf),
+    // piece length that isn't a power of two within the bounds
+    PieceLength(u64),
+    // file length differs from when the tree was walked
+    Changed(PathBuf),
+    Metainfo(MetainfoError),
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateError::Io(e) => write!(f, "{}", e),
+            CreateError::NoFiles => write!(f, "no files to add"),
+            CreateError::BadPath(p) => write!(f, "unusable path {:?}", p),
+            CreateError::PieceLength(l) => write!(
+                f,
+                "piece length {} must be a power of two from {} to {}",
+                l, MIN_PIECE_LEN, MAX_PIECE_LEN
+            ),
+            CreateError::Changed(p) => write!(f, "{:?} changed while hashing", p),
+            CreateError::Metainfo(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CreateError {}
+
+impl From<io::Error> for CreateError {
+    fn from(e: io::Error) -> Self {
+        CreateError::Io(e)
+    }
+}
+
+impl From<MetainfoError> for CreateError {
+    fn from(e: MetainfoError) -> Self {
+        CreateError::Metainfo(e)
+    }
+}
+
+impl From<bencode::Error> for CreateError {
+    fn from(e: bencode::Error) -> Self {
+        CreateError::Metainfo(MetainfoError::Bencode(e))
+    }
+}
+
+pub struct CreateOptions {
+    // chosen from the total size when none
+    pub piece_length: Option<u64>,
+    // tiers of tracker urls, the first url becomes announce
+    pub trackers: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub private: bool,
+    pub web_seeds: Vec<String>,
+    pub threads: usize,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            piece_length: None,
+            trackers: vec![],
+            comment: None,
+            created_by: Some(format!("bittorrent/{}", env!("CARGO_PKG_VERSION"))),
+            private: false,
+            web_seeds: vec![],
+            threads: num_cpus::get(),
+        }
+    }
+}
+
+// a file found on disk and its path inside the torrent
+struct Source {
+    disk: PathBuf,
+    path: Vec<String>,
+    len: u64,
+}
+
+fn utf8_name(path: &Path) -> Result<String, CreateError> {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) => Ok(n.to_string()),
+        None => Err(CreateError::BadPath(path.to_path_buf())),
+    }
+}
+
+// recursively collects regular files under dir, sorted by path. ancestors
+// holds the canonical paths of dir and the directories above it
+fn walk(
+    dir: &Path,
+    prefix: &[String],
+    ancestors: &mut Vec<PathBuf>,
+    out: &mut Vec<Source>,
+) -> Result<(), CreateError> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let disk = entry.path();
+        let mut path = prefix.to_vec();
+        path.push(utf8_name(&disk)?);
+        // follows symlinks
+        let meta = fs::metadata(&disk)?;
+        if meta.is_dir() {
+            // a symlink back up the tree would recurse forever
+            let real = fs::canonicalize(&disk)?;
+            if ancestors.contains(&real) {
+                continue;
+            }
+            ancestors.push(real);
+            walk(&disk, &path, ancestors, out)?;
+            ancestors.pop();
+        } else if meta.is_file() {
+            out.push(Source {
+                disk,
+                path,
+                len: meta.len(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// smallest power of two piece length giving at most TARGET_PIECES pieces
+pub fn auto_piece_length(total: u64) -> u64 {
+    let mut len = MIN_PIECE_LEN;
+    while len < MAX_PIECE_LEN && total.div_ceil(len) > TARGET_PIECES {
+        len *= 2;
+    }
+    len
+}
+
+// reads every file in order as one stream and hashes it piece by piece
+fn hash_sources(
+    sources: &[Source],
+    piece_len: u64,
+    threads: usize,
+) -> Result<Vec<u8>, CreateError> {
+    let total: u64 = sources.iter().map(|s| s.len).sum();
+    let num_pieces = total.div_ceil(piece_len) as usize;
+    let threads = threads.max(1);
+
+    let hasher = Arc::new(Hasher::new());
+    let hashes = Arc::new(Mutex::new(vec![[0; 20]; num_pieces]));
+    let handles = spawn_hash_pieces(&hasher, &hashes, threads);
+
+    let queue_piece = |index: usize, data: Vec<u8>| {
+        let piece = Piece {
+            index: index as u32,
+            data,
+            ..Default::default()
+        };
+        // keep at most a couple of pieces per thread in memory
+        let mut q = hasher
+            .empty
+            .wait_while(hasher.queue.lock().unwrap(), |q| q.len() >= threads * 2)
+            .unwrap();
+        q.push_back(vec![piece]);
+        hasher.loops.notify_one();
+    };
+
+    let mut result = Ok(());
+    let mut index = 0;
+    let mut buf = Vec::with_capacity(piece_len as usize);
+    'files: for source in sources {
+        let mut file = match File::open(&source.disk) {
+            Ok(f) => f,
+            Err(e) => {
+                result = Err(e.into());
+                break;
+            }
+        };
+        let mut read = 0;
+        loop {
+            let want = piece_len - buf.len() as u64;
+            let n = match (&mut file).take(want).read_to_end(&mut buf) {
+                Ok(n) => n as u64,
+                Err(e) => {
+                    result = Err(e.into());
+                    break 'files;
+                }
+            };
+            read += n;
+            if buf.len() as u64 == piece_len {
+                let data = std::mem::replace(&mut buf, Vec::with_capacity(piece_len as usize));
+                queue_piece(index, data);
+                index += 1;
+            }
+            if n < want {
+                break;
+            }
+        }
+        if read != source.len {
+            result = Err(CreateError::Changed(source.disk.clone()));
+            break;
+        }
+    }
+    if result.is_ok() && !buf.is_empty() {
+        queue_piece(index, buf);
+    }
+
+    // threads drain the queue before stopping
+    hasher.stop();
+    for t in handles {
+        t.join().unwrap();
+    }
+    result?;
+
+    let hashes = hashes.lock().unwrap();
+    Ok(hashes.concat())
+}
+
+// walks path and hashes its contents into a new metainfo
+pub fn create_torrent(path: &Path, opts: &CreateOptions) -> Result<Metainfo, CreateError> {
+    let root = fs::canonicalize(path)?;
+    let name = utf8_name(&root)?;
+
+    let meta = fs::metadata(&root)?;
+    let mut sources = vec![];
+    let single = meta.is_file();
+    if single {
+        sources.push(Source {
+            disk: root.clone(),
+            path: vec![name.clone()],
+            len: meta.len(),
+        });
+    } else {
+        walk(&root, &[], &mut vec![root.clone()], &mut sources)?;
+        if sources.is_empty() {
+            return Err(CreateError::NoFiles);
+        }
+    }
+
+    let total: u64 = sources.iter().map(|s| s.len).sum();
+    let piece_length = match opts.piece_length {
+        Some(l) if !(MIN_PIECE_LEN..=MAX_PIECE_LEN).contains(&l) || !l.is_power_of_two() => {
+            return Err(CreateError::PieceLength(l))
+        }
+        Some(l) => l,
+        None => auto_piece_length(total),
+    };
+    let pieces = hash_sources(&sources, piece_length, opts.threads)?;
+
+    let (length, files) = if single {
+        (Some(total), None)
+    } else {
+        let files = sources
+            .into_iter()
+            .map(|s| FileEntry {
+                length: s.len,
+                path: s.path,
+            })
+            .collect();
+        (None, Some(files))
+    };
+    let info = InfoDict {
+        piece_length,
+        pieces: ByteBuf(pieces),
+        name,
+        length,
+        files,
+        private: if opts.private { Some(true) } else { None },
+        meta_version: None,
+    };
+    info.validate()?;
+
+    let urls = opts.trackers.iter().flatten().collect::<Vec<_>>();
+    let announce_list = if urls.len() > 1 {
+        Some(opts.trackers.clone())
+    } else {
+        None
+    };
+    let creation_date = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .ok();
+
+    let info_bytes = bencode::to_bytes(&info)?;
+    let info_hash = get_info_hash(&info_bytes);
+    Ok(Metainfo {
+        announce: urls.first().map(|u| u.to_string()),
+        announce_list,
+        creation_date,
+        comment: opts.comment.clone(),
+        created_by: opts.created_by.clone(),
+        encoding: None,
+        info,
+        url_list: opts.web_seeds.clone(),
+        info_hash,
+        info_hash_v2: None,
+        info_bytes,
+    })
+}
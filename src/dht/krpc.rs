@@ -0,0 +1,152 @@
+// krpc messages, bencoded dicts sent over udp
+#![allow(dead_code)]
+
+use crate::bencode::ByteBuf;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+pub const QUERY: &[u8] = b"q";
+pub const RESPONSE: &[u8] = b"r";
+pub const ERROR: &[u8] = b"e";
+
+pub const PING: &str = "ping";
+pub const FIND_NODE: &str = "find_node";
+pub const GET_PEERS: &str = "get_peers";
+pub const ANNOUNCE_PEER: &str = "announce_peer";
+
+// error codes
+pub const GENERIC_ERROR: i64 = 201;
+pub const PROTOCOL_ERROR: i64 = 203;
+pub const METHOD_UNKNOWN: i64 = 204;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Args {
+    pub id: ByteBuf,
+    pub target: Option<ByteBuf>,
+    pub info_hash: Option<ByteBuf>,
+    pub port: Option<u16>,
+    pub token: Option<ByteBuf>,
+    // use the packet's source port instead of port
+    pub implied_port: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Resp {
+    pub id: ByteBuf,
+    // compact node infos
+    pub nodes: Option<ByteBuf>,
+    // compact peers
+    pub values: Option<Vec<ByteBuf>>,
+    pub token: Option<ByteBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Msg {
+    // transaction id echoed back in the reply
+    pub t: ByteBuf,
+    // q, r or e
+    pub y: ByteBuf,
+    pub q: Option<String>,
+    pub a: Option<Args>,
+    pub r: Option<Resp>,
+    // error code and message
+    pub e: Option<(i64, String)>,
+    // client version
+    pub v: Option<ByteBuf>,
+}
+
+impl Msg {
+    pub fn query(t: &[u8], method: &str, args: Args) -> Self {
+        Msg {
+            t: ByteBuf(t.to_vec()),
+            y: ByteBuf(QUERY.to_vec()),
+            q: Some(method.to_string()),
+            a: Some(args),
+            ..Msg::default()
+        }
+    }
+
+    pub fn response(t: &[u8], resp: Resp) -> Self {
+        Msg {
+            t: ByteBuf(t.to_vec()),
+            y: ByteBuf(RESPONSE.to_vec()),
+            r: Some(resp),
+            ..Msg::default()
+        }
+    }
+
+    pub fn error(t: &[u8], code: i64, msg: &str) -> Self {
+        Msg {
+            t: ByteBuf(t.to_vec()),
+            y: ByteBuf(ERROR.to_vec()),
+            e: Some((code, msg.to_string())),
+            ..Msg::default()
+        }
+    }
+}
+
+// node id and udp address of a dht node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeInfo {
+    pub id: [u8; 20],
+    pub addr: SocketAddr,
+}
+
+pub fn to_id(bytes: &[u8]) -> Option<[u8; 20]> {
+    if bytes.len() != 20 {
+        return None;
+    }
+    let mut id = [0; 20];
+    id.copy_from_slice(bytes);
+    Some(id)
+}
+
+// 6 byte ipv4 address and port
+pub fn encode_peer(addr: &SocketAddr) -> Option<Vec<u8>> {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            let mut out = ip.octets().to_vec();
+            out.extend_from_slice(&addr.port().to_be_bytes());
+            Some(out)
+        }
+        IpAddr::V6(_) => None,
+    }
+}
+
+pub fn decode_peer(bytes: &[u8]) -> Option<SocketAddr> {
+    if bytes.len() != 6 {
+        return None;
+    }
+    let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+    let port = u16::from_be_bytes([bytes[4], bytes[5]]);
+    Some(SocketAddr::new(IpAddr::from(ip), port))
+}
+
+// 26 bytes per node, id then compact address
+pub fn encode_nodes(nodes: &[NodeInfo]) -> Vec<u8> {
+    let mut out = vec![];
+    for node in nodes {
+        if let Some(addr) = encode_peer(&node.addr) {
+            out.extend_from_slice(&node.id);
+            out.extend_from_slice(&addr);
+        }
+    }
+    out
+}
+
+pub fn decode_nodes(bytes: &[u8]) -> Vec<NodeInfo> {
+    let mut nodes = vec![];
+    for chunk in bytes.chunks_exact(26) {
+        let addr = match decode_peer(&chunk[20..]) {
+            Some(a) if a.port() != 0 => a,
+            _ => continue,
+        };
+        nodes.push(NodeInfo {
+            id: to_id(&chunk[..20]).unwrap(),
+            addr,
+        });
+    }
+    nodes
+}
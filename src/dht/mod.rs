@@ -0,0 +1,719 @@
+// mainline dht node over udp, bep 5
+#![allow(dead_code)]
+
+pub mod krpc;
+pub mod routing;
+
+use self::{
+    krpc::*,
+    routing::{distance, Insert, Table, K},
+};
+
+use crate::bencode::{self, ByteBuf};
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::random;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::{
+    fs,
+    net::{lookup_host, UdpSocket},
+    sync::oneshot,
+    task::{self, JoinHandle},
+    time,
+};
+
+// queries in flight at once during a lookup
+const ALPHA: usize = 3;
+// most nodes queried by a single lookup
+const MAX_QUERIES: usize = 64;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+// token secrets rotate this often, tokens stay valid for two rotations
+const SECRET_ROTATION: Duration = Duration::from_secs(5 * 60);
+// announced peers are forgotten after this long
+const PEER_TTL: Duration = Duration::from_secs(30 * 60);
+const MAX_PEERS_PER_HASH: usize = 200;
+// most peers in a get_peers reply, keeps it within one packet
+const MAX_VALUES: usize = 50;
+// pause after a failed receive so a broken socket doesn't spin
+const RECV_BACKOFF: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_BOOTSTRAP: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+pub struct DhtConfig {
+    // falls back to any free port on the same ip if taken
+    pub bind: SocketAddr,
+    // host:port nodes used to join when the routing table is empty
+    pub bootstrap: Vec<String>,
+    // where the node id and routing table persist between runs
+    pub state: Option<PathBuf>,
+}
+
+// dht.dat in the user's config directory, none without a home directory
+fn default_state() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("bittorrent").join("dht.dat"))
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 6881)),
+            bootstrap: DEFAULT_BOOTSTRAP.iter().map(|s| s.to_string()).collect(),
+            state: default_state(),
+        }
+    }
+}
+
+// peers found by a lookup and the closest nodes that handed out tokens
+#[derive(Default)]
+pub struct Lookup {
+    pub peers: Vec<SocketAddr>,
+    pub tokens: Vec<(NodeInfo, Vec<u8>)>,
+}
+
+// persisted node id and routing table
+#[derive(Serialize, Deserialize)]
+struct State {
+    id: ByteBuf,
+    nodes: ByteBuf,
+}
+
+// announced peers and when each announced
+type PeerList = Vec<(SocketAddr, Instant)>;
+
+struct Secrets {
+    current: [u8; 20],
+    previous: [u8; 20],
+    rotated: Instant,
+}
+
+pub struct Dht {
+    pub id: [u8; 20],
+    socket: UdpSocket,
+    pub table: Mutex<Table>,
+    bootstrap: Vec<String>,
+    // peers announced to us, per info hash
+    peers: Mutex<HashMap<[u8; 20], PeerList>>,
+    // queries awaiting a reply by transaction id, with the address queried
+    pending: Mutex<HashMap<u16, (SocketAddr, oneshot::Sender<Msg>)>>,
+    next_tid: AtomicU16,
+    secrets: Mutex<Secrets>,
+    recv: Mutex<Option<JoinHandle<()>>>,
+}
+
+// binds a node per config, restoring any saved id and routing table
+pub async fn start(config: &DhtConfig) -> io::Result<Arc<Dht>> {
+    let (id, nodes) = match &config.state {
+        Some(path) => load(path).await.unwrap_or_else(|| (random(), vec![])),
+        None => (random(), vec![]),
+    };
+    let dht = match Dht::bind(config.bind, id, config.bootstrap.clone()).await {
+        Ok(d) => d,
+        Err(_) => {
+            let any = SocketAddr::new(config.bind.ip(), 0);
+            Dht::bind(any, id, config.bootstrap.clone()).await?
+        }
+    };
+    {
+        let mut table = dht.table.lock().unwrap();
+        for node in nodes {
+            table.insert(node);
+        }
+    }
+
+    Ok(dht)
+}
+
+async fn load(path: &Path) -> Option<([u8; 20], Vec<NodeInfo>)> {
+    let bytes = fs::read(path).await.ok()?;
+    let state: State = bencode::from_bytes(&bytes).ok()?;
+    Some((to_id(&state.id)?, decode_nodes(&state.nodes)))
+}
+
+// answers every datagram and hands replies to their waiting query
+async fn recv_loop(dht: Arc<Dht>) {
+    let mut buf = vec![0; 65536];
+    loop {
+        let (len, from) = match dht.socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(_) => {
+                time::sleep(RECV_BACKOFF).await;
+                continue;
+            }
+        };
+        let msg: Msg = match bencode::from_bytes(&buf[..len]) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+
+        if msg.y.as_slice() == QUERY {
+            let reply = dht.handle_query(&msg, from);
+            if let Ok(bytes) = bencode::to_bytes(&reply) {
+                let _ = dht.socket.send_to(&bytes, from).await;
+            }
+        } else if msg.y.as_slice() == RESPONSE || msg.y.as_slice() == ERROR {
+            dht.handle_reply(msg, from);
+        }
+    }
+}
+
+impl Dht {
+    pub async fn bind(
+        addr: SocketAddr,
+        id: [u8; 20],
+        bootstrap: Vec<String>,
+    ) -> io::Result<Arc<Self>> {
+        let socket = UdpSocket::bind(addr).await?;
+        let dht = Arc::new(Self {
+            id,
+            socket,
+            table: Mutex::new(Table::new(id)),
+            bootstrap,
+            peers: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_tid: AtomicU16::new(random()),
+            secrets: Mutex::new(Secrets {
+                current: random(),
+                previous: random(),
+                rotated: Instant::now(),
+            }),
+            recv: Mutex::new(None),
+        });
+        let handle = task::spawn(recv_loop(Arc::clone(&dht)));
+        *dht.recv.lock().unwrap() = Some(handle);
+
+        Ok(dht)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // stops answering, the node is dropped once every handle is
+    pub fn shutdown(&self) {
+        if let Some(h) = self.recv.lock().unwrap().take() {
+            h.abort();
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let state = State {
+            id: ByteBuf(self.id.to_vec()),
+            nodes: ByteBuf(encode_nodes(&self.table.lock().unwrap().nodes())),
+        };
+        let bytes = bencode::to_bytes(&state).map_err(io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(path, bytes).await
+    }
+
+    // token for an ip, sha1 of a rotating secret and the ip
+    fn token(&self, secret: &[u8; 20], from: &SocketAddr) -> Vec<u8> {
+        let mut hasher = Sha1::new();
+        hasher.update(secret);
+        match from.ip() {
+            IpAddr::V4(ip) => hasher.update(ip.octets()),
+            IpAddr::V6(ip) => hasher.update(ip.octets()),
+        }
+        hasher.finalize()[..8].to_vec()
+    }
+
+    fn secrets(&self) -> ([u8; 20], [u8; 20]) {
+        let mut s = self.secrets.lock().unwrap();
+        if s.rotated.elapsed() > SECRET_ROTATION {
+            s.previous = s.current;
+            s.current = random();
+            s.rotated = Instant::now();
+        }
+        (s.current, s.previous)
+    }
+
+    fn valid_token(&self, token: &[u8], from: &SocketAddr) -> bool {
+        let (current, previous) = self.secrets();
+        token == self.token(&current, from).as_slice()
+            || token == self.token(&previous, from).as_slice()
+    }
+
+    fn stored_peers(&self, info_hash: &[u8; 20]) -> Vec<SocketAddr> {
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get_mut(info_hash) {
+            Some(list) => {
+                list.retain(|(_, t)| t.elapsed() < PEER_TTL);
+                list.iter()
+                    .rev()
+                    .take(MAX_VALUES)
+                    .map(|(a, _)| *a)
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    fn store_peer(&self, info_hash: [u8; 20], addr: SocketAddr) {
+        let mut peers = self.peers.lock().unwrap();
+        let list = peers.entry(info_hash).or_default();
+        list.retain(|(a, t)| *a != addr && t.elapsed() < PEER_TTL);
+        if list.len() >= MAX_PEERS_PER_HASH {
+            list.remove(0);
+        }
+        list.push((addr, Instant::now()));
+    }
+
+    // records a node we heard from. when its bucket is full the stalest
+    // node there is pinged and replaced if it has gone away
+    fn add_node(self: &Arc<Self>, node: NodeInfo) {
+        let stale = match self.table.lock().unwrap().insert(node) {
+            Insert::Ping(stale) => stale,
+            Insert::Added | Insert::Full => return,
+        };
+        let dht = Arc::clone(self);
+        task::spawn(async move {
+            if dht.ping(stale.addr).await.is_none() {
+                dht.table.lock().unwrap().replace(&stale.id, node);
+            }
+        });
+    }
+
+    fn handle_query(self: &Arc<Self>, msg: &Msg, from: SocketAddr) -> Msg {
+        let t = msg.t.as_slice();
+        let args = match &msg.a {
+            Some(a) => a,
+            None => return Msg::error(t, PROTOCOL_ERROR, "missing arguments"),
+        };
+        let id = match to_id(&args.id) {
+            Some(i) => i,
+            None => return Msg::error(t, PROTOCOL_ERROR, "invalid id"),
+        };
+        let hash_arg = |arg: &Option<ByteBuf>| arg.as_ref().and_then(|a| to_id(a));
+        let mut resp = Resp {
+            id: ByteBuf(self.id.to_vec()),
+            ..Resp::default()
+        };
+
+        match msg.q.as_deref() {
+            Some(PING) => {}
+            Some(FIND_NODE) => {
+                let target = match hash_arg(&args.target) {
+                    Some(t) => t,
+                    None => return Msg::error(t, PROTOCOL_ERROR, "invalid target"),
+                };
+                let nodes = self.table.lock().unwrap().closest(&target, K);
+                resp.nodes = Some(ByteBuf(encode_nodes(&nodes)));
+            }
+            Some(GET_PEERS) => {
+                let info_hash = match hash_arg(&args.info_hash) {
+                    Some(h) => h,
+                    None => return Msg::error(t, PROTOCOL_ERROR, "invalid info_hash"),
+                };
+                let (current, _) = self.secrets();
+                resp.token = Some(ByteBuf(self.token(&current, &from)));
+                let peers = self.stored_peers(&info_hash);
+                if peers.is_empty() {
+                    let nodes = self.table.lock().unwrap().closest(&info_hash, K);
+                    resp.nodes = Some(ByteBuf(encode_nodes(&nodes)));
+                } else {
+                    let values = peers.iter().filter_map(encode_peer).map(ByteBuf).collect();
+                    resp.values = Some(values);
+                }
+            }
+            Some(ANNOUNCE_PEER) => {
+                let info_hash = match hash_arg(&args.info_hash) {
+                    Some(h) => h,
+                    None => return Msg::error(t, PROTOCOL_ERROR, "invalid info_hash"),
+                };
+                match &args.token {
+                    Some(token) if self.valid_token(token, &from) => {}
+                    _ => return Msg::error(t, PROTOCOL_ERROR, "bad token"),
+                }
+                let port = match (args.implied_port, args.port) {
+                    (Some(1), _) => from.port(),
+                    (_, Some(p)) if p != 0 => p,
+                    _ => return Msg::error(t, PROTOCOL_ERROR, "invalid port"),
+                };
+                self.store_peer(info_hash, SocketAddr::new(from.ip(), port));
+            }
+            _ => return Msg::error(t, METHOD_UNKNOWN, "method unknown"),
+        }
+        // anyone querying us is alive
+        self.add_node(NodeInfo { id, addr: from });
+
+        Msg::response(t, resp)
+    }
+
+    fn handle_reply(self: &Arc<Self>, msg: Msg, from: SocketAddr) {
+        if msg.t.len() != 2 {
+            return;
+        }
+        let tid = u16::from_be_bytes([msg.t[0], msg.t[1]]);
+        let waiting = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get(&tid) {
+                // replies must come from the address queried
+                Some((addr, _)) if *addr == from => pending.remove(&tid),
+                _ => None,
+            }
+        };
+        let tx = match waiting {
+            Some((_, tx)) => tx,
+            None => return,
+        };
+
+        if let Some(id) = msg.r.as_ref().and_then(|r| to_id(&r.id)) {
+            self.add_node(NodeInfo { id, addr: from });
+        }
+        let _ = tx.send(msg);
+    }
+
+    fn args(&self) -> Args {
+        Args {
+            id: ByteBuf(self.id.to_vec()),
+            ..Args::default()
+        }
+    }
+
+    // sends a query and waits for its response, none on error or timeout
+    async fn query(&self, addr: SocketAddr, method: &str, args: Args) -> Option<Resp> {
+        let tid = self.next_tid.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(tid, (addr, tx));
+
+        let bytes = bencode::to_bytes(&Msg::query(&tid.to_be_bytes(), method, args)).ok()?;
+        let reply = match self.socket.send_to(&bytes, addr).await {
+            Ok(_) => time::timeout(QUERY_TIMEOUT, rx).await.ok(),
+            Err(_) => None,
+        };
+        self.pending.lock().unwrap().remove(&tid);
+
+        match reply {
+            Some(Ok(msg)) => msg.r,
+            _ => {
+                self.table.lock().unwrap().failed(&addr);
+                None
+            }
+        }
+    }
+
+    // node id of the node at addr
+    pub async fn ping(&self, addr: SocketAddr) -> Option<[u8; 20]> {
+        to_id(&self.query(addr, PING, self.args()).await?.id)
+    }
+
+    pub async fn find_node(&self, addr: SocketAddr, target: [u8; 20]) -> Option<Vec<NodeInfo>> {
+        let args = Args {
+            target: Some(ByteBuf(target.to_vec())),
+            ..self.args()
+        };
+        let resp = self.query(addr, FIND_NODE, args).await?;
+        Some(decode_nodes(&resp.nodes.unwrap_or_default()))
+    }
+
+    pub async fn get_peers(&self, addr: SocketAddr, info_hash: [u8; 20]) -> Option<Resp> {
+        let args = Args {
+            info_hash: Some(ByteBuf(info_hash.to_vec())),
+            ..self.args()
+        };
+        self.query(addr, GET_PEERS, args).await
+    }
+
+    pub async fn announce_peer(
+        &self,
+        addr: SocketAddr,
+        info_hash: [u8; 20],
+        port: u16,
+        token: Vec<u8>,
+    ) -> Option<()> {
+        let args = Args {
+            info_hash: Some(ByteBuf(info_hash.to_vec())),
+            port: Some(port),
+            token: Some(ByteBuf(token)),
+            ..self.args()
+        };
+        self.query(addr, ANNOUNCE_PEER, args).await.map(|_| ())
+    }
+
+    // iterative search converging on the nodes closest to target, collecting
+    // peers and tokens along the way when get_peers is set
+    pub async fn lookup(self: &Arc<Self>, target: [u8; 20], get_peers: bool) -> Lookup {
+        let mut candidates = self.table.lock().unwrap().closest(&target, K);
+        let mut queried: HashSet<SocketAddr> = HashSet::new();
+        let mut result = Lookup::default();
+
+        loop {
+            candidates.sort_by_key(|n| distance(&n.id, &target));
+            candidates.dedup_by_key(|n| n.addr);
+            let batch: Vec<NodeInfo> = candidates
+                .iter()
+                .take(K)
+                .filter(|n| !queried.contains(&n.addr))
+                .take(ALPHA)
+                .copied()
+                .collect();
+            if batch.is_empty() || queried.len() >= MAX_QUERIES {
+                break;
+            }
+
+            let mut handles = vec![];
+            for node in &batch {
+                queried.insert(node.addr);
+                let dht = Arc::clone(self);
+                let addr = node.addr;
+                handles.push(task::spawn(async move {
+                    if get_peers {
+                        dht.get_peers(addr, target).await
+                    } else {
+                        let args = Args {
+                            target: Some(ByteBuf(target.to_vec())),
+                            ..dht.args()
+                        };
+                        dht.query(addr, FIND_NODE, args).await
+                    }
+                }));
+            }
+
+            for (node, handle) in batch.into_iter().zip(handles) {
+                let resp = match handle.await.ok().flatten() {
+                    Some(r) => r,
+                    None => {
+                        candidates.retain(|c| c.addr != node.addr);
+                        continue;
+                    }
+                };
+                if let Some(nodes) = &resp.nodes {
+                    candidates.extend(decode_nodes(nodes).into_iter().filter(|n| n.id != self.id));
+                }
+                for value in resp.values.iter().flatten() {
+                    if let Some(peer) = decode_peer(value) {
+                        result.peers.push(peer);
+                    }
+                }
+                if let Some(token) = resp.token {
+                    result.tokens.push((node, token.0));
+                }
+            }
+        }
+
+        result.peers.sort();
+        result.peers.dedup();
+        result.tokens.sort_by_key(|(n, _)| distance(&n.id, &target));
+        result.tokens.truncate(K);
+        result
+    }
+
+    // joins the network through the bootstrap nodes then fills the table
+    // with a lookup of our own id
+    pub async fn bootstrap(self: &Arc<Self>) {
+        let mut handles = vec![];
+        for host in &self.bootstrap {
+            let addrs = match lookup_host(host.as_str()).await {
+                Ok(a) => a,
+                Err(e) => {
+                    eprintln!("{} {:?}", e, host);
+                    continue;
+                }
+            };
+            for addr in addrs.filter(|a| a.is_ipv4()) {
+                let dht = Arc::clone(self);
+                handles.push(task::spawn(
+                    async move { dht.find_node(addr, dht.id).await },
+                ));
+            }
+        }
+        for h in handles {
+            let _ = h.await;
+        }
+        self.lookup(self.id, false).await;
+    }
+
+    // joins the network first if the routing table is too small to search
+    async fn ensure_bootstrapped(self: &Arc<Self>) {
+        if self.table.lock().unwrap().len() < K {
+            self.bootstrap().await;
+        }
+    }
+
+    // finds peers for info_hash without announcing ourselves
+    pub async fn find_peers(self: &Arc<Self>, info_hash: [u8; 20]) -> Vec<SocketAddr> {
+        self.ensure_bootstrapped().await;
+        self.lookup(info_hash, true).await.peers
+    }
+
+    // finds peers for info_hash and announces our port to the closest nodes
+    pub async fn announce(self: &Arc<Self>, info_hash: [u8; 20], port: u16) -> Vec<SocketAddr> {
+        self.ensure_bootstrapped().await;
+        let lookup = self.lookup(info_hash, true).await;
+
+        let mut handles = vec![];
+        for (node, token) in lookup.tokens {
+            let dht = Arc::clone(self);
+            handles.push(task::spawn(async move {
+                dht.announce_peer(node.addr, info_hash, port, token).await
+            }));
+        }
+        for h in handles {
+            let _ = h.await;
+        }
+
+        lookup.peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a node on loopback joining through the given nodes
+    async fn node(bootstrap: &[&Arc<Dht>]) -> Arc<Dht> {
+        let bootstrap = bootstrap
+            .iter()
+            .map(|n| n.local_addr().unwrap().to_string())
+            .collect();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        Dht::bind(addr, random(), bootstrap).await.unwrap()
+    }
+
+    fn knows(dht: &Dht, other: &Dht) -> bool {
+        dht.table
+            .lock()
+            .unwrap()
+            .nodes()
+            .iter()
+            .any(|n| n.id == other.id)
+    }
+
+    #[test]
+    fn table_splits_near_our_id() {
+        let mut table = Table::new([0; 20]);
+        let node = |first: u8, n: u8| {
+            let mut id = [0; 20];
+            id[0] = first;
+            id[19] = n;
+            NodeInfo {
+                id,
+                addr: SocketAddr::from((Ipv4Addr::LOCALHOST, u16::from_be_bytes([first, n]))),
+            }
+        };
+
+        // far nodes fill one bucket, the rest are turned away while fresh
+        for n in 0..K as u8 + 2 {
+            table.insert(node(0x80, n));
+        }
+        assert_eq!(table.len(), K);
+        assert!(matches!(table.insert(node(0x80, 99)), Insert::Full));
+
+        // nodes closer to us split buckets off and are all kept
+        for n in 0..K as u8 {
+            assert!(matches!(table.insert(node(0x40, n)), Insert::Added));
+            assert!(matches!(table.insert(node(0x01, n)), Insert::Added));
+        }
+        assert_eq!(table.len(), 3 * K);
+
+        // a few unanswered queries drop a node
+        let addr = node(0x01, 0).addr;
+        table.failed(&addr);
+        assert_eq!(table.len(), 3 * K);
+        table.failed(&addr);
+        table.failed(&addr);
+        assert_eq!(table.len(), 3 * K - 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ping_adds_both_ends() {
+        let a = node(&[]).await;
+        let b = node(&[]).await;
+
+        let id = a.ping(b.local_addr().unwrap()).await;
+        assert_eq!(id, Some(b.id));
+        assert!(knows(&a, &b));
+        assert!(knows(&b, &a));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bootstrap_finds_nodes_through_others() {
+        let a = node(&[]).await;
+        let b = node(&[&a]).await;
+        b.bootstrap().await;
+        let c = node(&[&a]).await;
+        c.bootstrap().await;
+
+        // c only knew a, b comes from a's find_node reply
+        assert!(knows(&c, &a));
+        assert!(knows(&c, &b));
+
+        let nodes = c.find_node(a.local_addr().unwrap(), b.id).await.unwrap();
+        assert!(nodes.iter().any(|n| n.id == b.id));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn announce_needs_a_valid_token() {
+        let a = node(&[]).await;
+        let b = node(&[]).await;
+        let c = node(&[]).await;
+        let b_addr = b.local_addr().unwrap();
+        let info_hash = random();
+
+        // no peers yet, so nodes and a token come back
+        let resp = a.get_peers(b_addr, info_hash).await.unwrap();
+        assert!(resp.values.is_none());
+        let token = resp.token.unwrap().0;
+
+        assert!(a
+            .announce_peer(b_addr, info_hash, 6000, b"bad".to_vec())
+            .await
+            .is_none());
+        // tokens are tied to the ip they were given to, not the node
+        assert!(c
+            .announce_peer(b_addr, info_hash, 6001, token.clone())
+            .await
+            .is_some());
+        assert!(a
+            .announce_peer(b_addr, info_hash, 6000, token)
+            .await
+            .is_some());
+
+        let resp = c.get_peers(b_addr, info_hash).await.unwrap();
+        let mut peers: Vec<SocketAddr> = resp
+            .values
+            .unwrap()
+            .iter()
+            .filter_map(|v| decode_peer(v))
+            .collect();
+        peers.sort();
+        let ip = IpAddr::from(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            peers,
+            vec![SocketAddr::new(ip, 6000), SocketAddr::new(ip, 6001)]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn announced_peers_are_found_by_lookup() {
+        let a = node(&[]).await;
+        let b = node(&[&a]).await;
+        let c = node(&[&a]).await;
+        let info_hash = random();
+
+        b.announce(info_hash, 7000).await;
+        let peers = c.find_peers(info_hash).await;
+        assert_eq!(peers, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 7000))]);
+    }
+}
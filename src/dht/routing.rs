@@ -0,0 +1,182 @@
+// kademlia routing table of k-buckets keyed by xor distance, bep 5
+#![allow(dead_code)]
+
+use super::krpc::NodeInfo;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+// nodes per bucket
+pub const K: usize = 8;
+// one bucket per bit of the id at most
+const MAX_BUCKETS: usize = 160;
+// nodes not heard from in this long are questionable and get pinged
+// before a new node may take their place
+const STALE: Duration = Duration::from_secs(15 * 60);
+// nodes failing this many queries in a row are dropped
+const MAX_FAILS: u32 = 3;
+
+pub fn distance(a: &[u8; 20], b: &[u8; 20]) -> [u8; 20] {
+    let mut d = [0; 20];
+    for i in 0..20 {
+        d[i] = a[i] ^ b[i];
+    }
+    d
+}
+
+// number of leading zero bits of a distance, the prefix shared with our id
+fn prefix_len(d: &[u8; 20]) -> usize {
+    for (i, byte) in d.iter().enumerate() {
+        if *byte != 0 {
+            return i * 8 + byte.leading_zeros() as usize;
+        }
+    }
+    MAX_BUCKETS - 1
+}
+
+struct Entry {
+    node: NodeInfo,
+    last_seen: Instant,
+    // queries gone unanswered since it was last heard from
+    fails: u32,
+    // a ping to see if it is still there is under way
+    pinged: bool,
+}
+
+// what became of a node offered to the table
+pub enum Insert {
+    Added,
+    // its bucket is full of good nodes
+    Full,
+    // its bucket is full but this node hasn't been heard from in a while,
+    // the new node takes its place if it doesn't answer a ping
+    Ping(NodeInfo),
+}
+
+// bucket i holds nodes sharing exactly i bits with our id, except the last
+// which holds everything closer and is split in two once it fills up
+pub struct Table {
+    pub id: [u8; 20],
+    buckets: Vec<Vec<Entry>>,
+}
+
+impl Table {
+    pub fn new(id: [u8; 20]) -> Self {
+        Self {
+            id,
+            buckets: vec![vec![]],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn index(&self, id: &[u8; 20]) -> usize {
+        prefix_len(&distance(&self.id, id)).min(self.buckets.len() - 1)
+    }
+
+    // moves the nodes of the last bucket that are closer still into a new one
+    fn split(&mut self) {
+        let last = self.buckets.len() - 1;
+        let id = self.id;
+        let (kept, closer) = self.buckets[last]
+            .drain(..)
+            .partition(|e| prefix_len(&distance(&id, &e.node.id)) == last);
+        self.buckets[last] = kept;
+        self.buckets.push(closer);
+    }
+
+    // records a node that was just heard from
+    pub fn insert(&mut self, node: NodeInfo) -> Insert {
+        if node.id == self.id {
+            return Insert::Full;
+        }
+        let now = Instant::now();
+        loop {
+            let index = self.index(&node.id);
+            let splittable = index == self.buckets.len() - 1 && self.buckets.len() < MAX_BUCKETS;
+            let bucket = &mut self.buckets[index];
+
+            // refresh a known node, keeping the bucket ordered by last seen
+            if let Some(i) = bucket.iter().position(|e| e.node.id == node.id) {
+                bucket.remove(i);
+                bucket.push(Entry {
+                    node,
+                    last_seen: now,
+                    fails: 0,
+                    pinged: false,
+                });
+                return Insert::Added;
+            }
+            if bucket.len() < K {
+                bucket.push(Entry {
+                    node,
+                    last_seen: now,
+                    fails: 0,
+                    pinged: false,
+                });
+                return Insert::Added;
+            }
+            // only the bucket our own id falls in is split
+            if splittable {
+                self.split();
+                continue;
+            }
+
+            // the least recently seen questionable node not already pinged
+            let stale = bucket
+                .iter_mut()
+                .find(|e| !e.pinged && now.duration_since(e.last_seen) > STALE);
+            return match stale {
+                Some(e) => {
+                    e.pinged = true;
+                    Insert::Ping(e.node)
+                }
+                None => Insert::Full,
+            };
+        }
+    }
+
+    // a node stopped answering and is given up on
+    pub fn replace(&mut self, old: &[u8; 20], node: NodeInfo) -> Insert {
+        self.remove(old);
+        self.insert(node)
+    }
+
+    pub fn remove(&mut self, id: &[u8; 20]) {
+        let index = self.index(id);
+        self.buckets[index].retain(|e| e.node.id != *id);
+    }
+
+    // a query to addr went unanswered, its node is dropped after several
+    pub fn failed(&mut self, addr: &SocketAddr) {
+        for bucket in &mut self.buckets {
+            for e in bucket.iter_mut().filter(|e| e.node.addr == *addr) {
+                e.fails += 1;
+            }
+            bucket.retain(|e| e.fails < MAX_FAILS);
+        }
+    }
+
+    // the n known nodes closest to target
+    pub fn closest(&self, target: &[u8; 20], n: usize) -> Vec<NodeInfo> {
+        let mut nodes = self.nodes();
+        nodes.sort_by_key(|node| distance(&node.id, target));
+        nodes.truncate(n);
+        nodes
+    }
+
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.buckets
+            .iter()
+            .flat_map(|b| b.iter().map(|e| e.node))
+            .collect()
+    }
+}
@@ -19,14 +19,14 @@ impl ByteField {
         self.arr.iter().filter(|x| **x < COMPLETE).count() == 0
     }
 
-    // returns an index which is marked empty
-    pub fn get_empty(&self) -> Option<usize> {
-        for i in 0..self.arr.len() {
-            if self.arr[i] == EMPTY {
-                return Some(i);
+    // bitfield message payload, high bit of the first byte is index 0
+    pub fn to_bitfield(&self) -> Vec<u8> {
+        let mut bits = vec![0; self.arr.len().div_ceil(8)];
+        for (i, x) in self.arr.iter().enumerate() {
+            if *x == COMPLETE {
+                bits[i / 8] |= 0x80 >> (i % 8);
             }
         }
-
-        None
+        bits
     }
 }
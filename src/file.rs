@@ -2,8 +2,8 @@
 #![allow(dead_code)]
 
 use crate::{
-    bencode::Item,
     hash::Hasher,
+    metainfo::InfoDict,
     tcp_bt::msg::{
         bytes::PIECE,
         structs::{Header, Piece},
@@ -12,7 +12,7 @@ use crate::{
     torrent::Torrent,
 };
 
-use std::{collections::BTreeMap, io::SeekFrom, ops::Deref, path::Path, str::from_utf8, sync::Arc};
+use std::{io::SeekFrom, ops::Deref, path::Path, sync::Arc};
 
 use tokio::{
     fs::{create_dir_all, File, OpenOptions},
@@ -155,20 +155,19 @@ pub async fn resume_torrent(torrent: &Arc<Torrent>, hasher: &Arc<Hasher>) {
 }
 
 // parses out each file from the info dict
-pub async fn parse_file(info: &BTreeMap<Vec<u8>, Item>) -> (Arc<Vec<FileSize>>, usize) {
+pub async fn parse_file(info: &InfoDict) -> (Arc<Vec<FileSize>>, usize) {
     // single file
-    if let Some(s) = info.get("length".as_bytes()) {
+    if let Some(file_len) = info.length {
         // file length
-        let file_len = s.get_int() as usize;
-        // name of the file
-        let filename = info.get("name".as_bytes()).unwrap().get_str();
+        let file_len = file_len as usize;
         // create file and return
-        let path = Path::new(std::str::from_utf8(&filename).unwrap());
+        let path = Path::new(&info.name);
         let dest = Arc::new(TokioMutex::new(
             OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path)
                 .await
                 .unwrap(),
@@ -181,25 +180,16 @@ pub async fn parse_file(info: &BTreeMap<Vec<u8>, Item>) -> (Arc<Vec<FileSize>>,
         (Arc::new(vec![file_size]), file_len)
     } else {
         // multifile
-        // get parent folder name and file dicts
-        let name = info.get("name".as_bytes()).unwrap().get_str();
-        let files = info.get("files".as_bytes()).unwrap().get_list();
         let mut ret: Vec<FileSize> = vec![];
-        // for each dict
-        for f in files {
-            let dict = f.get_dict();
-            // get length
-            let len = dict.get("length".as_bytes()).unwrap().get_int() as usize;
-            // parse out path
-            let mut path_list = dict.get("path".as_bytes()).unwrap().get_list();
-            // end filename
-            let end_file = path_list.pop().unwrap().get_str();
-            let filename = from_utf8(&end_file).unwrap();
-            // parent folders to the filename
-            let mut base = "./".to_string() + from_utf8(&name).unwrap();
+        // for each file, validated to have a non-empty path
+        for f in info.files.iter().flatten() {
+            let len = f.length as usize;
+            // end filename and parent folders to it
+            let (filename, path_list) = f.path.split_last().unwrap();
+            let mut base = "./".to_string() + &info.name;
             for folder in path_list {
-                let folder_name = "/".to_string() + from_utf8(&folder.get_str()).unwrap();
-                base.push_str(&folder_name);
+                base.push('/');
+                base.push_str(folder);
             }
             // create parents and file
             create_dir_all(base.clone()).await.unwrap();
@@ -210,6 +200,7 @@ pub async fn parse_file(info: &BTreeMap<Vec<u8>, Item>) -> (Arc<Vec<FileSize>>,
                     .read(true)
                     .write(true)
                     .create(true)
+                    .truncate(false)
                     .open(file_path)
                     .await
                     .unwrap(),
@@ -37,90 +37,47 @@ impl Hasher {
             brk: AtomicBool::new(false),
         }
     }
+
+    // stops the threads once the queue is drained, brk is set under the
+    // queue lock so a thread about to wait can't miss the wakeup
+    pub fn stop(&self) {
+        let _q = self.queue.lock().unwrap();
+        self.brk.store(true, Ordering::Relaxed);
+        self.loops.notify_all();
+    }
 }
 
-// spawns the hashing threads
-pub fn spawn_hash_write(
-    hasher: &Arc<Hasher>,
-    field: &Arc<Mutex<ByteField>>,
-    torrent: &Arc<Torrent>,
-    connector: &Arc<Connector>,
-    handle: Handle,
-    threads: usize,
-) -> Vec<JoinHandle<()>> {
+// spawns threads running job on each queued piece until stopped
+fn spawn_workers<F>(hasher: &Arc<Hasher>, threads: usize, job: F) -> Vec<JoinHandle<()>>
+where
+    F: Fn(Vec<Piece>) + Send + Sync + 'static,
+{
+    let job = Arc::new(job);
     let mut handles = vec![];
 
     for i in 0..threads {
         let hasher = Arc::clone(hasher);
-        let piece_field = Arc::clone(&field);
-        let torrent = Arc::clone(torrent);
-        let connector = Arc::clone(connector);
-        let files = Arc::clone(&torrent.files);
-        let handle = handle.clone();
+        let job = Arc::clone(&job);
 
         let builder = std::thread::Builder::new().name(format!("Hash{}", i));
         let handle = builder
-            .spawn(move || {
-                loop {
-                    let mut piece;
-                    {
-                        // critical section
-                        let mut guard = hasher
-                            .loops
-                            .wait_while(hasher.queue.lock().unwrap(), |q| {
-                                if hasher.brk.load(Ordering::Relaxed) {
-                                    return false;
-                                }
-                                q.is_empty()
-                            })
-                            .unwrap();
-                        if hasher.brk.load(Ordering::Relaxed) {
-                            break;
-                        }
-
-                        piece = match guard.pop_front() {
-                            Some(t) => t,
-                            None => break,
-                        }
-                    }
-                    hasher.empty.notify_all();
-                    let index = piece[0].index as usize;
-                    let mut flat_piece = Vec::with_capacity(torrent.piece_len);
-                    piece.sort_by_key(|x| x.offset);
-                    for s in &piece {
-                        flat_piece.extend_from_slice(&s.data); // assumes ordered by offset
-                    }
-
-                    let mut hasher = Sha1::new();
-                    hasher.update(flat_piece);
-                    let piece_hash = hasher.finalize().to_vec();
-
-                    if piece_hash
-                        .iter()
-                        .zip(&torrent.hashes[index])
-                        .filter(|&(a, b)| *a == *b)
-                        .count()
-                        != 20
-                    {
-                        {
-                            // critical section
-                            // unreserve piece
-                            let mut pf = piece_field.lock().unwrap();
-                            pf.arr[index] = EMPTY;
-                            // notify waiting connections
-                            connector.piece.notify_one();
-                        }
-                        continue;
-                    }
-                    for s in &piece {
-                        handle.block_on(write_subpiece(s, torrent.piece_len, &files));
-                    }
-                    {
-                        // critical section
-                        let mut pf = piece_field.lock().unwrap();
-                        pf.arr[index] = COMPLETE;
+            .spawn(move || loop {
+                let piece;
+                {
+                    // critical section
+                    let mut guard = hasher
+                        .loops
+                        .wait_while(hasher.queue.lock().unwrap(), |q| {
+                            q.is_empty() && !hasher.brk.load(Ordering::Relaxed)
+                        })
+                        .unwrap();
+                    piece = match guard.pop_front() {
+                        Some(t) => t,
+                        None => break,
                     }
                 }
+                hasher.empty.notify_all();
+                job(piece);
             })
             .unwrap();
         handles.push(handle);
@@ -129,6 +86,58 @@ pub fn spawn_hash_write(
     handles
 }
 
+// spawns the hashing threads
+pub fn spawn_hash_write(
+    hasher: &Arc<Hasher>,
+    field: &Arc<Mutex<ByteField>>,
+    torrent: &Arc<Torrent>,
+    connector: &Arc<Connector>,
+    handle: Handle,
+    threads: usize,
+) -> Vec<JoinHandle<()>> {
+    let piece_field = Arc::clone(field);
+    let torrent = Arc::clone(torrent);
+    let connector = Arc::clone(connector);
+    let files = Arc::clone(&torrent.files);
+
+    spawn_workers(hasher, threads, move |mut piece| {
+        let index = piece[0].index as usize;
+        let mut flat_piece = Vec::with_capacity(torrent.piece_len);
+        piece.sort_by_key(|x| x.offset);
+        for s in &piece {
+            flat_piece.extend_from_slice(&s.data); // assumes ordered by offset
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.update(flat_piece);
+        let piece_hash = hasher.finalize().to_vec();
+
+        if piece_hash
+            .iter()
+            .zip(&torrent.hashes[index])
+            .filter(|&(a, b)| *a == *b)
+            .count()
+            != 20
+        {
+            // critical section
+            // unreserve piece
+            let mut pf = piece_field.lock().unwrap();
+            pf.arr[index] = EMPTY;
+            // notify waiting connections
+            connector.piece.notify_one();
+            return;
+        }
+        for s in &piece {
+            handle.block_on(write_subpiece(s, torrent.piece_len, &files));
+        }
+        {
+            // critical section
+            let mut pf = piece_field.lock().unwrap();
+            pf.arr[index] = COMPLETE;
+        }
+    })
+}
+
 // splits hashes from 1d rasterized to 2d
 pub fn split_hashes(hashes: &[u8]) -> Vec<Vec<u8>> {
     let num_pieces: usize = hashes.len() / 20;
@@ -139,3 +148,26 @@ pub fn split_hashes(hashes: &[u8]) -> Vec<Vec<u8>> {
 
     split_hashes
 }
+
+// spawns threads that hash whole pieces into hashes[index], used when creating torrents
+pub fn spawn_hash_pieces(
+    hasher: &Arc<Hasher>,
+    hashes: &Arc<Mutex<Vec<[u8; 20]>>>,
+    threads: usize,
+) -> Vec<JoinHandle<()>> {
+    let hashes = Arc::clone(hashes);
+
+    spawn_workers(hasher, threads, move |piece| {
+        let mut sha = Sha1::new();
+        for s in &piece {
+            sha.update(&s.data);
+        }
+        let mut piece_hash = [0; 20];
+        piece_hash.copy_from_slice(&sha.finalize());
+        {
+            // critical section
+            let mut h = hashes.lock().unwrap();
+            h[piece[0].index as usize] = piece_hash;
+        }
+    })
+}
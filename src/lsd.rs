@@ -0,0 +1,345 @@
+// local service discovery, bep 14 multicast announces on the lan
+#![allow(dead_code)]
+
+use crate::bencode::dump::hex;
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_channel::Sender;
+use rand::random;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::{
+    net::UdpSocket,
+    task::{self, JoinHandle},
+    time,
+};
+
+pub const LSD_V4: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 192, 152, 143), 6771);
+pub const LSD_V6: SocketAddrV6 = SocketAddrV6::new(
+    Ipv6Addr::new(0xff15, 0, 0, 0, 0, 0, 0xefc0, 0x988f),
+    6771,
+    0,
+    0,
+);
+
+// how often every active torrent is announced
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// announces from one peer for one torrent closer together are ignored
+const MIN_RECV_INTERVAL: Duration = Duration::from_secs(60);
+// most info hashes in a single announce, keeps it within one datagram
+const MAX_HASHES: usize = 20;
+// pause after a failed receive so a broken socket doesn't spin
+const RECV_BACKOFF: Duration = Duration::from_millis(100);
+
+pub struct LsdConfig {
+    // multicast groups announced to and listened on, none disables a family
+    pub v4: Option<SocketAddrV4>,
+    pub v6: Option<SocketAddrV6>,
+    // ipv4 interface to join and send on, unspecified for the default route
+    pub interface: Ipv4Addr,
+    // ipv6 interface index, 0 for the default
+    pub interface_v6: u32,
+}
+
+impl Default for LsdConfig {
+    fn default() -> Self {
+        Self {
+            v4: Some(LSD_V4),
+            v6: Some(LSD_V6),
+            interface: Ipv4Addr::UNSPECIFIED,
+            interface_v6: 0,
+        }
+    }
+}
+
+// a torrent being announced and where its discovered peers go
+struct Active {
+    port: u16,
+    tx: Sender<SocketAddr>,
+}
+
+pub struct Lsd {
+    // sockets joined to each group, with the group address
+    sockets: Vec<(Arc<UdpSocket>, SocketAddr)>,
+    // identifies our own announces when they loop back
+    cookie: String,
+    active: Mutex<HashMap<[u8; 20], Active>>,
+    // last accepted announce per peer ip and info hash
+    received: Mutex<HashMap<(IpAddr, [u8; 20]), Instant>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+// udp socket sharing the group's port with other clients on this host
+fn multicast_socket(group: SocketAddr, config: &LsdConfig) -> io::Result<UdpSocket> {
+    let socket = match group {
+        SocketAddr::V4(g) => {
+            let s = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            s.set_reuse_address(true)?;
+            s.bind(&SockAddr::from(SocketAddrV4::new(
+                Ipv4Addr::UNSPECIFIED,
+                g.port(),
+            )))?;
+            s.join_multicast_v4(g.ip(), &config.interface)?;
+            s.set_multicast_if_v4(&config.interface)?;
+            s.set_multicast_loop_v4(true)?;
+            s
+        }
+        SocketAddr::V6(g) => {
+            let s = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            s.set_only_v6(true)?;
+            s.set_reuse_address(true)?;
+            s.bind(&SockAddr::from(SocketAddrV6::new(
+                Ipv6Addr::UNSPECIFIED,
+                g.port(),
+                0,
+                0,
+            )))?;
+            s.join_multicast_v6(g.ip(), config.interface_v6)?;
+            s.set_multicast_if_v6(config.interface_v6)?;
+            s.set_multicast_loop_v6(true)?;
+            s
+        }
+    };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// bt-search request for some info hashes
+fn search_msg(group: &SocketAddr, port: u16, hashes: &[[u8; 20]], cookie: &str) -> Vec<u8> {
+    let mut msg = format!(
+        "BT-SEARCH * HTTP/1.1\r\nHost: {}\r\nPort: {}\r\n",
+        group, port
+    );
+    for h in hashes {
+        msg.push_str(&format!("Infohash: {}\r\n", hex(h)));
+    }
+    msg.push_str(&format!("cookie: {}\r\n\r\n\r\n", cookie));
+    msg.into_bytes()
+}
+
+// port, info hashes and cookie of a bt-search request
+fn parse_search(bytes: &[u8]) -> Option<(u16, Vec<[u8; 20]>, Option<String>)> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let mut lines = text.split("\r\n");
+    if lines.next()? != "BT-SEARCH * HTTP/1.1" {
+        return None;
+    }
+
+    let mut port = None;
+    let mut hashes = vec![];
+    let mut cookie = None;
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((n, v)) => (n.trim().to_ascii_lowercase(), v.trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            "port" => port = value.parse().ok(),
+            "infohash" => {
+                if let Some(h) = parse_hash(value) {
+                    hashes.push(h);
+                }
+            }
+            "cookie" => cookie = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    Some((port.filter(|p| *p != 0)?, hashes, cookie))
+}
+
+fn parse_hash(s: &str) -> Option<[u8; 20]> {
+    if s.len() != 40 || !s.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+// joins the configured groups, ok as long as one family works
+pub async fn start(config: &LsdConfig) -> io::Result<Arc<Lsd>> {
+    let groups = config
+        .v4
+        .map(SocketAddr::V4)
+        .into_iter()
+        .chain(config.v6.map(SocketAddr::V6));
+    let mut sockets = vec![];
+    let mut error = io::Error::other("no lsd groups configured");
+    for group in groups {
+        match multicast_socket(group, config) {
+            Ok(s) => sockets.push((Arc::new(s), group)),
+            Err(e) => error = e,
+        }
+    }
+    if sockets.is_empty() {
+        return Err(error);
+    }
+
+    let lsd = Arc::new(Lsd {
+        sockets,
+        cookie: hex(&random::<[u8; 8]>()),
+        active: Mutex::new(HashMap::new()),
+        received: Mutex::new(HashMap::new()),
+        handles: Mutex::new(vec![]),
+    });
+
+    let mut handles = vec![];
+    for (socket, _) in &lsd.sockets {
+        handles.push(task::spawn(recv_loop(Arc::clone(&lsd), Arc::clone(socket))));
+    }
+    let announcer = Arc::clone(&lsd);
+    handles.push(task::spawn(async move {
+        loop {
+            time::sleep(ANNOUNCE_INTERVAL).await;
+            announcer.announce().await;
+        }
+    }));
+    *lsd.handles.lock().unwrap() = handles;
+
+    Ok(lsd)
+}
+
+// passes peers announcing an active torrent on to that torrent
+async fn recv_loop(lsd: Arc<Lsd>, socket: Arc<UdpSocket>) {
+    let mut buf = vec![0; 1500];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(_) => {
+                time::sleep(RECV_BACKOFF).await;
+                continue;
+            }
+        };
+        let (port, hashes, cookie) = match parse_search(&buf[..len]) {
+            Some(s) => s,
+            None => continue,
+        };
+        if cookie.as_deref() == Some(lsd.cookie.as_str()) {
+            continue;
+        }
+
+        let peer = SocketAddr::new(from.ip(), port);
+        for hash in hashes {
+            let tx = match lsd.active.lock().unwrap().get(&hash) {
+                Some(a) => a.tx.clone(),
+                None => continue,
+            };
+            {
+                let mut r = lsd.received.lock().unwrap();
+                let now = Instant::now();
+                if let Some(last) = r.get(&(from.ip(), hash)) {
+                    if now.duration_since(*last) < MIN_RECV_INTERVAL {
+                        continue;
+                    }
+                }
+                r.insert((from.ip(), hash), now);
+            }
+            let _ = tx.send(peer).await;
+        }
+    }
+}
+
+impl Lsd {
+    // starts announcing a torrent listening on port, discovered peers go to tx
+    pub async fn add(&self, info_hash: [u8; 20], port: u16, tx: Sender<SocketAddr>) {
+        self.active
+            .lock()
+            .unwrap()
+            .insert(info_hash, Active { port, tx });
+        self.send(port, &[info_hash]).await;
+    }
+
+    pub fn remove(&self, info_hash: &[u8; 20]) {
+        self.active.lock().unwrap().remove(info_hash);
+    }
+
+    // announces every active torrent, one message per listen port
+    pub async fn announce(&self) {
+        let mut by_port: HashMap<u16, Vec<[u8; 20]>> = HashMap::new();
+        for (hash, a) in self.active.lock().unwrap().iter() {
+            by_port.entry(a.port).or_default().push(*hash);
+        }
+        for (port, hashes) in by_port {
+            for chunk in hashes.chunks(MAX_HASHES) {
+                self.send(port, chunk).await;
+            }
+        }
+    }
+
+    async fn send(&self, port: u16, hashes: &[[u8; 20]]) {
+        for (socket, group) in &self.sockets {
+            let msg = search_msg(group, port, hashes, &self.cookie);
+            if let Err(e) = socket.send_to(&msg, group).await {
+                eprintln!("lsd {} {}", e, group);
+            }
+        }
+    }
+
+    pub fn shutdown(&self) {
+        for h in self.handles.lock().unwrap().drain(..) {
+            h.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ipv4 only on loopback, on a port of its own so tests don't mix
+    fn loopback(port: u16) -> LsdConfig {
+        LsdConfig {
+            v4: Some(SocketAddrV4::new(*LSD_V4.ip(), port)),
+            v6: None,
+            interface: Ipv4Addr::LOCALHOST,
+            interface_v6: 0,
+        }
+    }
+
+    #[test]
+    fn search_round_trips() {
+        let group = SocketAddr::V4(LSD_V4);
+        let hashes = [[0xab; 20], [0x01; 20]];
+        let msg = search_msg(&group, 6881, &hashes, "c00k1e");
+        let (port, parsed, cookie) = parse_search(&msg).unwrap();
+        assert_eq!(port, 6881);
+        assert_eq!(parsed, hashes);
+        assert_eq!(cookie.as_deref(), Some("c00k1e"));
+
+        assert!(parse_search(b"NOTIFY * HTTP/1.1\r\nPort: 1\r\n\r\n").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn announces_reach_other_nodes_on_loopback() {
+        let config = loopback(16881);
+        let a = start(&config).await.unwrap();
+        let b = start(&config).await.unwrap();
+        let info_hash = random();
+        let (a_tx, a_rx) = async_channel::unbounded();
+        let (b_tx, _b_rx) = async_channel::unbounded();
+
+        a.add(info_hash, 5000, a_tx).await;
+        b.add(info_hash, 6000, b_tx).await;
+
+        // b's announce arrives, a's own loops back and is skipped
+        let peer = time::timeout(Duration::from_secs(2), a_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(peer, SocketAddr::from((Ipv4Addr::LOCALHOST, 6000)));
+        time::sleep(Duration::from_millis(200)).await;
+        assert!(a_rx.is_empty());
+
+        a.shutdown();
+        b.shutdown();
+    }
+}
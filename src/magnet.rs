@@ -0,0 +1,248 @@
+// magnet uri parsing
+#![allow(dead_code)]
+
+use crate::{bencode::dump::hex, metainfo::Metainfo};
+
+use std::{fmt, net::SocketAddr};
+
+use tokio::net::lookup_host;
+
+#[derive(Debug)]
+pub enum MagnetError {
+    // doesn't start with magnet:?
+    Scheme,
+    NoInfoHash,
+    BadInfoHash(String),
+    // bad percent escape or non utf8 value
+    BadValue(String),
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MagnetError::Scheme => write!(f, "not a magnet uri"),
+            MagnetError::NoInfoHash => write!(f, "magnet has no urn:btih info hash"),
+            MagnetError::BadInfoHash(h) => write!(f, "invalid info hash {:?}", h),
+            MagnetError::BadValue(v) => write!(f, "invalid magnet value {:?}", v),
+        }
+    }
+}
+
+impl std::error::Error for MagnetError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Magnet {
+    pub info_hash: [u8; 20],
+    // sha256 v2 info hash from a btmh multihash
+    pub info_hash_v2: Option<[u8; 32]>,
+    // dn, display name until the metadata arrives
+    pub name: Option<String>,
+    // tr, tracker urls
+    pub trackers: Vec<String>,
+    // x.pe, host:port peers to try directly
+    pub peers: Vec<String>,
+    // ws, web seed urls
+    pub web_seeds: Vec<String>,
+}
+
+fn hex_val(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+// decodes %xx escapes and + as space
+fn percent_decode(value: &str) -> Result<String, MagnetError> {
+    let bad = || MagnetError::BadValue(value.to_string());
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hi = bytes.get(i + 1).and_then(|c| hex_val(*c)).ok_or_else(bad)?;
+                let lo = bytes.get(i + 2).and_then(|c| hex_val(*c)).ok_or_else(bad)?;
+                out.push(hi << 4 | lo);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| bad())
+}
+
+// N bytes as 2N hex digits
+fn parse_hex<const N: usize>(hash: &str) -> Option<[u8; N]> {
+    let bytes = hash.as_bytes();
+    if bytes.len() != N * 2 {
+        return None;
+    }
+    let mut out = [0; N];
+    for (i, pair) in bytes.chunks(2).enumerate() {
+        out[i] = hex_val(pair[0])? << 4 | hex_val(pair[1])?;
+    }
+    Some(out)
+}
+
+// percent encodes everything but rfc 3986 unreserved characters
+fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+// multihash prefix for a 32 byte sha2-256 digest
+const SHA256_MULTIHASH: &str = "1220";
+
+// 32 rfc 4648 base32 characters
+fn parse_base32(hash: &str) -> Option<[u8; 20]> {
+    let bytes = hash.as_bytes();
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut out = [0; 20];
+    let mut acc = 0_u64;
+    let mut bits = 0;
+    let mut n = 0;
+    for c in bytes {
+        let v = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        acc = acc << 5 | v as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out[n] = (acc >> bits) as u8;
+            n += 1;
+        }
+    }
+    Some(out)
+}
+
+impl Magnet {
+    pub fn parse(uri: &str) -> Result<Self, MagnetError> {
+        let query = match uri.strip_prefix("magnet:?") {
+            Some(q) => q,
+            None => return Err(MagnetError::Scheme),
+        };
+
+        let mut magnet = Magnet::default();
+        let mut found = false;
+        for param in query.split('&') {
+            let (key, value) = match param.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            // numbered keys like tr.1 repeat the same parameter
+            let key = match key.rsplit_once('.') {
+                Some((k, n)) if !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()) => k,
+                _ => key,
+            };
+            let value = percent_decode(value)?;
+            match key {
+                "xt" if value.starts_with("urn:btmh:") => {
+                    let hash = &value["urn:btmh:".len()..];
+                    // only sha2-256 multihashes are used by bittorrent v2
+                    magnet.info_hash_v2 = match hash.strip_prefix(SHA256_MULTIHASH) {
+                        Some(h) => parse_hex(h),
+                        None => None,
+                    };
+                    if magnet.info_hash_v2.is_none() {
+                        return Err(MagnetError::BadInfoHash(hash.to_string()));
+                    }
+                }
+                "xt" => {
+                    // other urns are skipped
+                    let hash = match value.strip_prefix("urn:btih:") {
+                        Some(h) => h,
+                        None => continue,
+                    };
+                    if found {
+                        continue;
+                    }
+                    magnet.info_hash = match parse_hex(hash).or_else(|| parse_base32(hash)) {
+                        Some(h) => h,
+                        None => return Err(MagnetError::BadInfoHash(hash.to_string())),
+                    };
+                    found = true;
+                }
+                "dn" => magnet.name = Some(value),
+                "tr" => magnet.trackers.push(value),
+                "x.pe" => magnet.peers.push(value),
+                "ws" => magnet.web_seeds.push(value),
+                _ => {}
+            }
+        }
+        // peers are found and metadata fetched by the v1 hash, a btmh alone
+        // isn't enough
+        if !found {
+            return Err(MagnetError::NoInfoHash);
+        }
+
+        Ok(magnet)
+    }
+
+    // magnet for a loaded torrent's metainfo
+    pub fn from_meta(meta: &Metainfo) -> Self {
+        Magnet {
+            info_hash: meta.info_hash,
+            info_hash_v2: meta.info_hash_v2,
+            name: Some(meta.info.name.clone()),
+            trackers: meta.trackers().into_iter().map(String::from).collect(),
+            peers: vec![],
+            web_seeds: meta.url_list.clone(),
+        }
+    }
+
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("magnet:?xt=urn:btih:{}", hex(&self.info_hash));
+        if let Some(v2) = &self.info_hash_v2 {
+            uri.push_str(&format!("&xt=urn:btmh:{}{}", SHA256_MULTIHASH, hex(v2)));
+        }
+        if let Some(name) = &self.name {
+            uri.push_str(&format!("&dn={}", percent_encode(name)));
+        }
+        for tr in &self.trackers {
+            uri.push_str(&format!("&tr={}", percent_encode(tr)));
+        }
+        for ws in &self.web_seeds {
+            uri.push_str(&format!("&ws={}", percent_encode(ws)));
+        }
+        for pe in &self.peers {
+            uri.push_str(&format!("&x.pe={}", percent_encode(pe)));
+        }
+        uri
+    }
+
+    // resolves the x.pe peers, skipping any that don't resolve
+    pub async fn peer_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = vec![];
+        for peer in &self.peers {
+            match lookup_host(peer.as_str()).await {
+                Ok(a) => addrs.extend(a),
+                Err(e) => eprintln!("{} {:?}", e, peer),
+            }
+        }
+        addrs
+    }
+}
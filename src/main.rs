@@ -1,13 +1,266 @@
 // main function
 mod bencode;
+mod create;
+mod dht;
 mod field;
 mod file;
 mod hash;
+mod lsd;
+mod magnet;
+mod metainfo;
+mod peer_id;
 mod tcp_bt;
 mod torrent;
 mod tracker;
 
-use torrent::Torrent;
+use bencode::{decode::parse, dump};
+use create::{create_torrent, CreateOptions};
+use lsd::LsdConfig;
+use magnet::Magnet;
+use metainfo::Metainfo;
+use tcp_bt::{
+    metadata::fetch_metadata,
+    picker::{self, PiecePicker, RarestFirst},
+};
+use torrent::{Torrent, QUEUE_DEPTH};
+
+use std::{net::SocketAddr, path::PathBuf};
+
+// prints a bencoded file as indented text or json
+async fn dump(args: &[String]) {
+    let arg = if let Some(s) = args.iter().find(|a| !a.starts_with("--")) {
+        s
+    } else {
+        eprintln!("usage: bittorrent dump <file> [--json]");
+        return;
+    };
+    let bytes: Vec<u8> = match tokio::fs::read(arg).await {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{} {:?}", e, arg);
+            return;
+        }
+    };
+    let item = match parse(&bytes) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{} {:?}", e, arg);
+            return;
+        }
+    };
+
+    if args.iter().any(|a| a == "--json") {
+        println!("{}", dump::json(&item));
+    } else {
+        println!("{}", dump::pretty(&item));
+    }
+}
+
+const CREATE_USAGE: &str = "usage: bittorrent create <path> [-o out.torrent] \
+[-t url[,url...]]... [-w url]... [-c comment] [-l piece_length] [-p]";
+
+// builds a .torrent from a file or directory
+async fn create(args: &[String]) {
+    let mut path = None;
+    let mut output = None;
+    let mut opts = CreateOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let flag = arg.as_str();
+        if flag == "-p" || flag == "--private" {
+            opts.private = true;
+            continue;
+        }
+        if !flag.starts_with('-') {
+            path = Some(PathBuf::from(arg));
+            continue;
+        }
+        let value = match iter.next() {
+            Some(v) => v.clone(),
+            None => {
+                eprintln!("{}", CREATE_USAGE);
+                return;
+            }
+        };
+        match flag {
+            "-o" | "--output" => output = Some(PathBuf::from(value)),
+            // each flag is a tier, urls in a tier are comma separated
+            "-t" | "--tracker" => opts
+                .trackers
+                .push(value.split(',').map(|u| u.to_string()).collect()),
+            "-w" | "--web-seed" => opts.web_seeds.push(value),
+            "-c" | "--comment" => opts.comment = Some(value),
+            "-l" | "--piece-length" => match value.parse() {
+                Ok(l) => opts.piece_length = Some(l),
+                Err(_) => {
+                    eprintln!("invalid piece length {:?}", value);
+                    return;
+                }
+            },
+            _ => {
+                eprintln!("{}", CREATE_USAGE);
+                return;
+            }
+        }
+    }
+    let path = match path {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", CREATE_USAGE);
+            return;
+        }
+    };
+
+    // hashing blocks on file reads and worker threads
+    let src = path.clone();
+    let meta = match tokio::task::spawn_blocking(move || create_torrent(&src, &opts))
+        .await
+        .unwrap()
+    {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{} {:?}", e, path);
+            return;
+        }
+    };
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.torrent", meta.info.name)));
+    let bytes = match meta.to_bytes() {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = tokio::fs::write(&output, bytes).await {
+        eprintln!("{} {:?}", e, output);
+        return;
+    }
+
+    println!("wrote {:?}", output);
+    println!("info hash {}", dump::hex(&meta.info_hash));
+}
+
+const DOWNLOAD_USAGE: &str = "usage: bittorrent <torrent|magnet> \
+[--lsd-group addr:port]... [--lsd-interface ip|index] \
+[--picker rarest|sequential|random] [--queue-depth blocks]";
+
+// settings from download flags
+struct DownloadOptions {
+    lsd: LsdConfig,
+    picker: Box<dyn PiecePicker>,
+    queue_depth: usize,
+}
+
+// e.g. running lsd on loopback with
+// --lsd-group 239.192.152.143:6771 --lsd-interface 127.0.0.1
+fn download_options(args: &[String]) -> Result<DownloadOptions, String> {
+    let mut lsd = LsdConfig::default();
+    let mut picker: Box<dyn PiecePicker> = Box::new(RarestFirst);
+    let mut queue_depth = QUEUE_DEPTH;
+    let mut iter = args.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter.next().ok_or_else(|| DOWNLOAD_USAGE.to_string())?;
+        match flag.as_str() {
+            // replaces the default group of the same family
+            "--lsd-group" => match value.parse() {
+                Ok(SocketAddr::V4(g)) => lsd.v4 = Some(g),
+                Ok(SocketAddr::V6(g)) => lsd.v6 = Some(g),
+                Err(_) => return Err(format!("invalid lsd group {:?}", value)),
+            },
+            // an ipv4 address or an ipv6 interface index
+            "--lsd-interface" => match (value.parse(), value.parse()) {
+                (Ok(ip), _) => lsd.interface = ip,
+                (_, Ok(index)) => lsd.interface_v6 = index,
+                _ => return Err(format!("invalid lsd interface {:?}", value)),
+            },
+            "--picker" => match picker::from_name(value) {
+                Some(p) => picker = p,
+                None => return Err(format!("invalid picker {:?}", value)),
+            },
+            "--queue-depth" => match value.parse() {
+                Ok(d) if d > 0 => queue_depth = d,
+                _ => return Err(format!("invalid queue depth {:?}", value)),
+            },
+            _ => return Err(DOWNLOAD_USAGE.to_string()),
+        }
+    }
+    Ok(DownloadOptions {
+        lsd,
+        picker,
+        queue_depth,
+    })
+}
+
+// fetches a magnet's info dict from peers then downloads it like a torrent file
+async fn magnet(uri: &str, options: DownloadOptions) {
+    let magnet = match Magnet::parse(uri) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{} {:?}", e, uri);
+            return;
+        }
+    };
+    let name = match &magnet.name {
+        Some(n) => n.clone(),
+        None => dump::hex(&magnet.info_hash),
+    };
+    println!("fetching metadata for {}", name);
+
+    // the same id fetches the metadata and downloads
+    let peer_id = peer_id::generate();
+    let info = match fetch_metadata(&magnet, peer_id).await {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let mut meta = match Metainfo::from_info(&info) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{} {:?}", e, name);
+            return;
+        }
+    };
+    if !magnet.trackers.is_empty() {
+        meta.announce_list = Some(magnet.trackers.iter().map(|t| vec![t.clone()]).collect());
+    }
+    meta.url_list = magnet.web_seeds.clone();
+
+    let mut torrent = Torrent::from_meta(meta).await;
+    torrent.peer_id = peer_id;
+    torrent.peers = magnet.peer_addrs().await;
+    torrent.picker = options.picker;
+    torrent.queue_depth = options.queue_depth;
+    // private torrents keep lsd disabled
+    if torrent.lsd.is_some() {
+        torrent.lsd = Some(options.lsd);
+    }
+    torrent.start().await;
+}
+
+// prints a magnet link for a torrent file
+async fn magnet_link(args: &[String]) {
+    let arg = if let Some(s) = args.first() {
+        s
+    } else {
+        eprintln!("usage: bittorrent magnet <file>");
+        return;
+    };
+    let bytes: Vec<u8> = match tokio::fs::read(arg).await {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{} {:?}", e, arg);
+            return;
+        }
+    };
+    // only the metainfo, building a Torrent would create its files
+    match Metainfo::from_bytes(&bytes) {
+        Ok(m) => println!("{}", Magnet::from_meta(&m).to_uri()),
+        Err(e) => eprintln!("{} {:?}", e, arg),
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -20,6 +273,33 @@ async fn main() {
         return;
     };
 
+    // subcommands
+    if arg == "dump" {
+        dump(&args[2..]).await;
+        return;
+    }
+    if arg == "magnet" {
+        magnet_link(&args[2..]).await;
+        return;
+    }
+    if arg == "create" {
+        create(&args[2..]).await;
+        return;
+    }
+
+    let options = match download_options(&args[2..]) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    if arg.starts_with("magnet:") {
+        magnet(arg, options).await;
+        return;
+    }
+
     // read and parse torrent file
     let bytes: Vec<u8> = match tokio::fs::read(arg).await {
         Ok(b) => b,
@@ -30,6 +310,17 @@ async fn main() {
     };
 
     // download torrent
-    let torrent = Torrent::new(&bytes).await;
+    let mut torrent = match Torrent::new(&bytes).await {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{} {:?}", e, arg);
+            return;
+        }
+    };
+    torrent.picker = options.picker;
+    torrent.queue_depth = options.queue_depth;
+    if torrent.lsd.is_some() {
+        torrent.lsd = Some(options.lsd);
+    }
     torrent.start().await;
 }
@@ -0,0 +1,312 @@
+// typed and validated .torrent metainfo
+#![allow(dead_code)]
+
+use crate::{
+    bencode::{self, decode::find_span, from_bytes, to_bytes, ByteBuf},
+    tracker::{get_info_hash, get_info_hash_v2},
+};
+
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+#[derive(Debug)]
+pub enum MetainfoError {
+    Bencode(bencode::Error),
+    ZeroPieceLength,
+    // length of the pieces string
+    PiecesLength(usize),
+    PieceCount { expected: usize, found: usize },
+    // both or neither of length and files
+    FileMode,
+    NoFiles,
+    BadPath(String),
+    // file lengths add up past u64
+    TooLong,
+}
+
+impl fmt::Display for MetainfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetainfoError::Bencode(e) => write!(f, "{}", e),
+            MetainfoError::ZeroPieceLength => write!(f, "piece length is zero"),
+            MetainfoError::PiecesLength(l) => {
+                write!(f, "pieces length {} is not a multiple of 20", l)
+            }
+            MetainfoError::PieceCount { expected, found } => {
+                write!(f, "expected {} piece hashes, found {}", expected, found)
+            }
+            MetainfoError::FileMode => write!(f, "info must have exactly one of length or files"),
+            MetainfoError::NoFiles => write!(f, "files list is empty"),
+            MetainfoError::BadPath(p) => write!(f, "invalid file path {:?}", p),
+            MetainfoError::TooLong => write!(f, "total length overflows"),
+        }
+    }
+}
+
+impl std::error::Error for MetainfoError {}
+
+impl From<bencode::Error> for MetainfoError {
+    fn from(e: bencode::Error) -> Self {
+        MetainfoError::Bencode(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileEntry {
+    pub length: u64,
+    pub path: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InfoDict {
+    #[serde(rename = "piece length")]
+    pub piece_length: u64,
+    pub pieces: ByteBuf,
+    pub name: String,
+    pub length: Option<u64>,
+    pub files: Option<Vec<FileEntry>>,
+    pub private: Option<bool>,
+    // 2 for v2 and hybrid torrents
+    #[serde(rename = "meta version")]
+    pub meta_version: Option<i64>,
+}
+
+impl InfoDict {
+    // sum of every file's length, validate checks it fits
+    pub fn total_len(&self) -> u64 {
+        match (&self.length, &self.files) {
+            (Some(l), _) => *l,
+            (None, Some(files)) => files.iter().map(|f| f.length).sum(),
+            (None, None) => 0,
+        }
+    }
+
+    pub fn num_pieces(&self) -> usize {
+        self.pieces.len() / 20
+    }
+
+    pub fn validate(&self) -> Result<(), MetainfoError> {
+        if self.piece_length == 0 {
+            return Err(MetainfoError::ZeroPieceLength);
+        }
+        if !self.pieces.len().is_multiple_of(20) {
+            return Err(MetainfoError::PiecesLength(self.pieces.len()));
+        }
+        check_component(&self.name)?;
+        match (&self.length, &self.files) {
+            (Some(_), None) => {}
+            (None, Some(files)) => {
+                if files.is_empty() {
+                    return Err(MetainfoError::NoFiles);
+                }
+                let mut total: u64 = 0;
+                for f in files {
+                    total = total.checked_add(f.length).ok_or(MetainfoError::TooLong)?;
+                    if f.path.is_empty() {
+                        return Err(MetainfoError::BadPath(String::new()));
+                    }
+                    for c in &f.path {
+                        check_component(c)?;
+                    }
+                }
+            }
+            _ => return Err(MetainfoError::FileMode),
+        }
+        let expected = self.total_len().div_ceil(self.piece_length) as usize;
+        if expected != self.num_pieces() {
+            return Err(MetainfoError::PieceCount {
+                expected,
+                found: self.num_pieces(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// file names must not escape the download directory
+fn check_component(c: &str) -> Result<(), MetainfoError> {
+    if c.is_empty() || c == "." || c == ".." || c.contains('/') || c.contains('\\') {
+        return Err(MetainfoError::BadPath(c.to_string()));
+    }
+    Ok(())
+}
+
+// url-list may be a single url or a list of them
+fn string_or_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a string or list of strings")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<String>, E> {
+            if v.is_empty() {
+                return Ok(vec![]);
+            }
+            Ok(vec![v.to_string()])
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<String>, E> {
+            match std::str::from_utf8(v) {
+                Ok(s) => self.visit_str(s),
+                Err(_) => Err(de::Error::invalid_value(de::Unexpected::Bytes(v), &self)),
+            }
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<String>, A::Error> {
+            let mut list = vec![];
+            while let Some(s) = seq.next_element()? {
+                list.push(s);
+            }
+            Ok(list)
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    #[serde(rename = "announce-list")]
+    pub announce_list: Option<Vec<Vec<String>>>,
+    #[serde(rename = "creation date")]
+    pub creation_date: Option<i64>,
+    pub comment: Option<String>,
+    #[serde(rename = "created by")]
+    pub created_by: Option<String>,
+    pub encoding: Option<String>,
+    pub info: InfoDict,
+    #[serde(
+        rename = "url-list",
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub url_list: Vec<String>,
+    // sha1 of the info dict as encoded in the source file
+    #[serde(skip)]
+    pub info_hash: [u8; 20],
+    // sha256 of the info dict, only for v2 and hybrid torrents
+    #[serde(skip)]
+    pub info_hash_v2: Option<[u8; 32]>,
+    // the info dict as encoded in the source file, with keys info doesn't know
+    #[serde(skip)]
+    pub info_bytes: Vec<u8>,
+}
+
+impl Metainfo {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MetainfoError> {
+        let mut meta: Metainfo = from_bytes(bytes)?;
+        meta.info.validate()?;
+
+        // hash the info dict exactly as it appears in the file
+        let span = find_span(bytes, &[b"info"])
+            .map_err(bencode::Error::from)?
+            .unwrap_or_default();
+        meta.info_hash = get_info_hash(&bytes[span.clone()]);
+        if meta.info.meta_version == Some(2) {
+            meta.info_hash_v2 = Some(get_info_hash_v2(&bytes[span.clone()]));
+        }
+        meta.info_bytes = bytes[span].to_vec();
+
+        Ok(meta)
+    }
+
+    // wraps a bare info dict, e.g. one fetched from peers for a magnet link
+    pub fn from_info(bytes: &[u8]) -> Result<Self, MetainfoError> {
+        let info: InfoDict = from_bytes(bytes)?;
+        info.validate()?;
+        let info_hash_v2 = if info.meta_version == Some(2) {
+            Some(get_info_hash_v2(bytes))
+        } else {
+            None
+        };
+
+        Ok(Metainfo {
+            announce: None,
+            announce_list: None,
+            creation_date: None,
+            comment: None,
+            created_by: None,
+            encoding: None,
+            info,
+            url_list: vec![],
+            info_hash: get_info_hash(bytes),
+            info_hash_v2,
+            info_bytes: bytes.to_vec(),
+        })
+    }
+
+    // canonical bencoding of the metainfo, info_hash is not stored. the info
+    // dict is written as it was read so unknown keys survive and the info hash
+    // holds, changes to info only show when info_bytes is empty
+    pub fn to_bytes(&self) -> Result<Vec<u8>, bencode::Error> {
+        let mut bytes = to_bytes(self)?;
+        if !self.info_bytes.is_empty() {
+            if let Some(span) = find_span(&bytes, &[b"info"])? {
+                bytes.splice(span, self.info_bytes.iter().copied());
+            }
+        }
+        Ok(bytes)
+    }
+
+    // every tracker url, announce first then each announce-list tier in order
+    pub fn trackers(&self) -> Vec<&str> {
+        let mut list: Vec<&str> = vec![];
+        if let Some(a) = &self.announce {
+            list.push(a);
+        }
+        for tier in self.announce_list.iter().flatten() {
+            for url in tier {
+                if !list.contains(&url.as_str()) {
+                    list.push(url);
+                }
+            }
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &[u8] = b"d5:filesld6:lengthi1e4:pathl1:aeed6:lengthi1e4:pathl1:beee\
+4:name1:x12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa6:sourcei7ee";
+
+    #[test]
+    fn unknown_info_keys_survive() {
+        let mut bytes = b"d4:info".to_vec();
+        bytes.extend_from_slice(INFO);
+        bytes.push(b'e');
+
+        let meta = Metainfo::from_bytes(&bytes).unwrap();
+        assert_eq!(meta.info.total_len(), 2);
+        let out = meta.to_bytes().unwrap();
+        assert_eq!(out, bytes);
+        assert_eq!(
+            Metainfo::from_bytes(&out).unwrap().info_hash,
+            meta.info_hash
+        );
+    }
+
+    #[test]
+    fn overflowing_length_is_rejected() {
+        // each length fits an i64, together they pass u64
+        let file = format!("d6:lengthi{}e4:pathl1:aee", i64::MAX);
+        let info = format!(
+            "d5:filesl{0}{0}{0}e4:name1:x12:piece lengthi16384e6:pieces0:e",
+            file
+        );
+        assert!(matches!(
+            Metainfo::from_info(info.as_bytes()),
+            Err(MetainfoError::TooLong)
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+// azureus style peer ids, ours and naming the clients behind others
+#![allow(dead_code)]
+
+use rand::{distributions::Alphanumeric, Rng};
+
+// -<client code><version>- then random characters
+pub const PREFIX: &[u8; 8] = b"-RB0010-";
+
+// azureus style client codes
+const CLIENTS: &[(&[u8; 2], &str)] = &[
+    (b"RB", "bittorrent"),
+    (b"AZ", "Vuze"),
+    (b"BC", "BitComet"),
+    (b"BI", "BiglyBT"),
+    (b"BT", "BitTorrent"),
+    (b"DE", "Deluge"),
+    (b"FD", "Free Download Manager"),
+    (b"KT", "KTorrent"),
+    (b"LT", "libtorrent (rakshasa)"),
+    (b"lt", "libtorrent (rasterbar)"),
+    (b"qB", "qBittorrent"),
+    (b"TR", "Transmission"),
+    (b"UM", "uTorrent Mac"),
+    (b"UT", "uTorrent"),
+    (b"WW", "WebTorrent"),
+    (b"XL", "Xunlei"),
+];
+
+// a fresh id, one per session
+pub fn generate() -> [u8; 20] {
+    let mut id = [0; 20];
+    id[..8].copy_from_slice(PREFIX);
+    let mut rng = rand::thread_rng();
+    for byte in &mut id[8..] {
+        *byte = rng.sample(Alphanumeric);
+    }
+    id
+}
+
+// client name and version for logging, e.g. qBittorrent 4.2.5.0
+pub fn client_name(id: &[u8; 20]) -> String {
+    // azureus style, -qB4250-
+    if id[0] == b'-' && id[7] == b'-' && id[1..7].iter().all(u8::is_ascii_alphanumeric) {
+        let version = id[3..7]
+            .iter()
+            .map(|c| (*c as char).to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        return match CLIENTS.iter().find(|(code, _)| **code == id[1..3]) {
+            Some((_, name)) => format!("{} {}", name, version),
+            None => format!("{} {}", String::from_utf8_lossy(&id[1..3]), version),
+        };
+    }
+
+    // mainline style, M4-3-6--
+    if id[0] == b'M' {
+        let rest = String::from_utf8_lossy(&id[1..8]);
+        let version: Vec<&str> = rest.split('-').filter(|s| !s.is_empty()).collect();
+        if !version.is_empty()
+            && version
+                .iter()
+                .all(|v| v.chars().all(|c| c.is_ascii_digit()))
+        {
+            return format!("BitTorrent {}", version.join("."));
+        }
+    }
+
+    "unknown".to_string()
+}
@@ -3,32 +3,61 @@
 use super::seed::Peer;
 
 use crate::{
-    field::{constant::*, ByteField},
-    tcp_bt::{fetch::torrent_fetcher, parse::Parser, seed::torrent_seeder, send_handshake},
+    field::ByteField,
+    hash::Hasher,
+    peer_id::client_name,
+    tcp_bt::{
+        ext::ExtPeer,
+        fast::{allowed_fast_msgs, allowed_fast_set, have_msg, ALLOWED_FAST_K},
+        fetch::{torrent_fetcher, Download},
+        parse::Parser,
+        pex::{spawn_pex_sender, Pex},
+        seed::torrent_seeder,
+        send_handshake,
+        state::{disconnected, Availability, PeerPieces},
+    },
     torrent::Torrent,
 };
 
-use std::sync::{
-    atomic::{AtomicBool, AtomicU32},
-    Arc, Condvar, Mutex,
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc, Condvar, Mutex,
+    },
 };
 
 use tokio::{
+    io::AsyncWriteExt,
     net::TcpStream,
-    sync::Mutex as TokioMutex,
+    sync::{broadcast, Mutex as TokioMutex},
     task::{self, JoinHandle},
 };
 
 pub struct Connector {
     pub piece: Condvar,
     pub brk: AtomicBool,
+    pub pex: Arc<Pex>,
+    // how many connected peers have each piece
+    pub avail: Mutex<Availability>,
+    // pieces under way and abandoned ones with blocks kept, by piece index
+    pub downloads: Mutex<HashMap<usize, Download>>,
+    // blocks received that other connections also requested, to cancel
+    pub arrived: broadcast::Sender<(u32, u32)>,
+    // where finished pieces go to be checked and written
+    pub hasher: Arc<Hasher>,
 }
 
 impl Connector {
-    pub fn new() -> Self {
+    pub fn new(pex: Arc<Pex>, num_pieces: usize, hasher: &Arc<Hasher>) -> Self {
         Self {
             piece: Condvar::new(),
             brk: AtomicBool::new(false),
+            pex,
+            avail: Mutex::new(Availability::new(num_pieces)),
+            downloads: Mutex::new(HashMap::new()),
+            arrived: broadcast::channel(1024).0,
+            hasher: Arc::clone(hasher),
         }
     }
 }
@@ -47,7 +76,12 @@ pub async fn spawn_connector_task(
     let field = Arc::clone(field);
     let count = Arc::clone(count);
 
-    return task::spawn(async move {
+    task::spawn(async move {
+        // outgoing peers are shared with others over pex
+        let outgoing = match &peer {
+            Peer::Addr(addr) => Some(*addr),
+            Peer::Stream(_) => None,
+        };
         let mut stream = match peer {
             Peer::Addr(addr) => match TcpStream::connect(&addr).await {
                 Ok(s) => s,
@@ -56,15 +90,64 @@ pub async fn spawn_connector_task(
             Peer::Stream(s) => s,
         };
 
-        match send_handshake(&mut stream, torrent.info_hash, torrent.info_hash).await {
-            Some(_) => {}
+        let theirs = match send_handshake(&mut stream, torrent.info_hash, torrent.peer_id).await {
+            Some(h) => h,
             None => return,
+        };
+        let addr = stream.peer_addr().ok();
+        if let Some(a) = addr {
+            println!("connected to {} ({})", a, client_name(&theirs.peer_id));
+        }
+        let fast = theirs.fast();
+        let allowed_fast: HashSet<u32> = match (fast, addr) {
+            (true, Some(a)) => allowed_fast_set(
+                a.ip(),
+                &torrent.info_hash,
+                torrent.num_pieces,
+                ALLOWED_FAST_K,
+            )
+            .into_iter()
+            .collect(),
+            _ => HashSet::new(),
+        };
+
+        // what we have and may serve while choking, interest follows the
+        // peer's bitfield
+        let mut intro = have_msg(&field, fast);
+        intro.extend(allowed_fast_msgs(&allowed_fast));
+        // our extended handshake goes first, the peer may only ever answer
+        let extensions = theirs.extensions();
+        if extensions {
+            intro.extend(torrent.extensions.handshake_msg());
+        }
+        if stream.write_all(&intro).await.is_err() {
+            return;
         }
 
+        // negotiated once and shared by every fetch and seed on this connection
+        let ext_peer = Arc::new(TokioMutex::new(ExtPeer {
+            addr,
+            peer_id: theirs.peer_id,
+            reserved: theirs.reserved,
+            fast,
+            allowed_fast,
+            sent: extensions,
+            pieces: PeerPieces::new(torrent.num_pieces),
+            ..ExtPeer::default()
+        }));
+
         let (reader, writer) = stream.into_split();
         let am_reader = Arc::new(TokioMutex::new(reader));
         let am_writer = Arc::new(TokioMutex::new(writer));
 
+        if let Some(addr) = outgoing {
+            connector.pex.connected(addr);
+        }
+        // private torrents don't share their peers
+        let pex_sender = torrent
+            .pex
+            .then(|| spawn_pex_sender(&am_writer, &ext_peer, &connector));
+
         let mut complete = false;
         task::block_in_place(|| {
             let f = field.lock().unwrap();
@@ -72,36 +155,24 @@ pub async fn spawn_connector_task(
                 complete = true;
             }
         });
-        if complete {
+        if !complete {
+            torrent_fetcher(
+                &am_reader, &am_writer, &parser, &torrent, &field, &connector, &count, &ext_peer,
+            )
+            .await;
+        } else {
             torrent_seeder(
-                &am_reader, &am_writer, &parser, &torrent, &field, &connector, &count,
+                &am_reader, &am_writer, &parser, &torrent, &field, &connector, &count, &ext_peer,
             )
             .await;
-            return;
         }
 
-        let v = torrent_fetcher(
-            &am_reader, &am_writer, &parser, &torrent, &field, &connector, &count,
-        )
-        .await;
-        // resets in progress pieces
-        task::block_in_place(|| {
-            let mut f = field.lock().unwrap();
-            for i in &v {
-                if f.arr[*i] == IN_PROGRESS {
-                    f.arr[*i] = EMPTY;
-                    connector.piece.notify_one();
-                }
-            }
-            for i in &f.arr {
-                if *i == EMPTY {
-                    complete = false;
-                }
-            }
-        });
-        torrent_seeder(
-            &am_reader, &am_writer, &parser, &torrent, &field, &connector, &count,
-        )
-        .await;
-    });
+        if let Some(s) = pex_sender {
+            s.abort();
+        }
+        disconnected(&ext_peer, &connector).await;
+        if let Some(addr) = addr {
+            connector.pex.disconnected(addr);
+        }
+    })
 }
@@ -0,0 +1,258 @@
+// bep 10 extension protocol, extended handshake and handler registry
+#![allow(dead_code)]
+
+use super::{
+    msg::structs::Extended,
+    state::{Choke, PeerPieces},
+};
+
+use crate::{
+    bencode::{self, decode, ByteBuf, Item},
+    torrent::Torrent,
+};
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use async_channel::Receiver;
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::Mutex as TokioMutex,
+    task::{self, JoinHandle},
+};
+
+// extended message id of the extended handshake itself
+pub const EXT_HANDSHAKE: u8 = 0;
+// outstanding requests we accept from a peer, advertised as reqq
+pub const REQQ: u32 = 250;
+
+// dictionary sent as the payload of the extended handshake
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ExtHandshake {
+    // extension names to the ids the sender wants them sent with, 0 disables
+    pub m: BTreeMap<String, i64>,
+    // client name and version
+    pub v: Option<String>,
+    // local tcp listen port
+    pub p: Option<u16>,
+    pub reqq: Option<u32>,
+    // the receiver's ip as the sender sees it, 4 or 16 bytes
+    pub yourip: Option<ByteBuf>,
+    pub metadata_size: Option<i64>,
+}
+
+impl ExtHandshake {
+    // decodes a peer's handshake field by field, one it sent malformed or
+    // out of range is left out rather than losing the rest
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let item = decode::parse(bytes).ok()?;
+        item.get_dict()?;
+        let int = |key: &str| item.get(key).and_then(Item::get_int);
+        let str = |key: &str| item.get(key).and_then(Item::get_str);
+
+        let mut m = BTreeMap::new();
+        if let Some(dict) = item.get("m").and_then(Item::get_dict) {
+            for (name, id) in dict {
+                if let (Ok(name), Some(id)) = (std::str::from_utf8(name), id.get_int()) {
+                    m.insert(name.to_string(), id);
+                }
+            }
+        }
+        Some(Self {
+            m,
+            v: str("v").map(|v| String::from_utf8_lossy(v).into_owned()),
+            p: int("p").and_then(|p| u16::try_from(p).ok()),
+            reqq: int("reqq").and_then(|r| u32::try_from(r).ok()),
+            yourip: str("yourip").map(|ip| ByteBuf(ip.to_vec())),
+            metadata_size: int("metadata_size"),
+        })
+    }
+
+    // id to send the named extension's messages with, none if unsupported
+    pub fn id(&self, name: &str) -> Option<u8> {
+        match self.m.get(name) {
+            Some(&i) if i > 0 && i <= u8::MAX as i64 => Some(i as u8),
+            _ => None,
+        }
+    }
+
+    pub fn yourip(&self) -> Option<IpAddr> {
+        let ip = self.yourip.as_ref()?;
+        match ip.len() {
+            4 => Some(IpAddr::from(Ipv4Addr::from([ip[0], ip[1], ip[2], ip[3]]))),
+            16 => {
+                let mut v6 = [0; 16];
+                v6.copy_from_slice(ip);
+                Some(IpAddr::from(Ipv6Addr::from(v6)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// negotiated extension state of a single connection
+#[derive(Default)]
+pub struct ExtPeer {
+    // remote end of the connection
+    pub addr: Option<SocketAddr>,
+    // peer id and reserved bits from the peer's handshake
+    pub peer_id: [u8; 20],
+    pub reserved: [u8; 8],
+    // the peer's extended handshake once received
+    pub handshake: Option<ExtHandshake>,
+    // whether ours has been sent
+    pub sent: bool,
+    // both ends set the fast extension bit
+    pub fast: bool,
+    // pieces we let the peer request while choking it
+    pub allowed_fast: HashSet<u32>,
+    // pieces the peer lets us request while it chokes us
+    pub peer_allowed_fast: HashSet<u32>,
+    // pieces the peer suggested we download, most recent last
+    pub suggested: Vec<u32>,
+    // pieces the peer has, from its bitfield or have all/none and haves
+    pub pieces: PeerPieces,
+    // who is choking and interested in whom
+    pub choke: Choke,
+    // the connection ended, later messages are ignored
+    pub closed: bool,
+}
+
+impl ExtPeer {
+    // whether the peer negotiated the named extension
+    pub fn supports(&self, name: &str) -> bool {
+        self.id(name).is_some()
+    }
+
+    pub fn id(&self, name: &str) -> Option<u8> {
+        self.handshake.as_ref()?.id(name)
+    }
+
+    // wire bytes of a message for the named extension, none if the peer lacks it
+    pub fn message(&self, name: &str, payload: Vec<u8>) -> Option<Vec<u8>> {
+        Some(Extended::new(self.id(name)?, payload).as_bytes())
+    }
+}
+
+// a protocol extension negotiated through the extended handshake
+pub trait Extension: Send + Sync {
+    // name advertised in the m dictionary, e.g. ut_metadata
+    fn name(&self) -> &'static str;
+
+    // called once the peer's handshake arrives, returns wire messages to send
+    fn on_handshake(&self, _peer: &ExtPeer) -> Vec<Vec<u8>> {
+        vec![]
+    }
+
+    // payload of a message the peer sent with our id for this extension,
+    // returns wire messages to send back
+    fn on_message(&self, peer: &ExtPeer, payload: &[u8]) -> Vec<Vec<u8>>;
+}
+
+// extensions we support, our id for each is its position plus one
+#[derive(Default)]
+pub struct Registry {
+    exts: Vec<Arc<dyn Extension>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self { exts: vec![] }
+    }
+
+    // adds an extension, returning the id peers will send its messages with
+    pub fn register(&mut self, ext: Arc<dyn Extension>) -> u8 {
+        self.exts.push(ext);
+        self.exts.len() as u8
+    }
+
+    pub fn id(&self, name: &str) -> Option<u8> {
+        let i = self.exts.iter().position(|e| e.name() == name)?;
+        Some(i as u8 + 1)
+    }
+
+    // our extended handshake advertising every registered extension
+    pub fn handshake(&self) -> ExtHandshake {
+        let m = self
+            .exts
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.name().to_string(), i as i64 + 1))
+            .collect();
+        ExtHandshake {
+            m,
+            v: Some(format!("bittorrent {}", env!("CARGO_PKG_VERSION"))),
+            reqq: Some(REQQ),
+            ..ExtHandshake::default()
+        }
+    }
+
+    pub fn handshake_msg(&self) -> Vec<u8> {
+        let payload = bencode::to_bytes(&self.handshake()).unwrap();
+        Extended::new(EXT_HANDSHAKE, payload).as_bytes()
+    }
+
+    // routes an extended message to its handler, returning wire messages to send back
+    pub fn dispatch(&self, peer: &mut ExtPeer, msg: &Extended) -> Vec<Vec<u8>> {
+        let mut out = vec![];
+        if msg.ext_id == EXT_HANDSHAKE {
+            // later handshakes update the earlier one
+            let hs = match ExtHandshake::parse(&msg.payload) {
+                Some(h) => h,
+                None => return out,
+            };
+            peer.handshake = Some(hs);
+            // answer with ours if we haven't already
+            if !peer.sent {
+                peer.sent = true;
+                out.push(self.handshake_msg());
+            }
+            for ext in &self.exts {
+                out.extend(ext.on_handshake(peer));
+            }
+            return out;
+        }
+        // messages for extensions we never advertised are ignored
+        match self.exts.get(msg.ext_id as usize - 1) {
+            Some(ext) => ext.on_message(peer, &msg.payload),
+            None => out,
+        }
+    }
+}
+
+// handles a connection's extended messages as the parser forwards them
+pub fn spawn_ext_handler(
+    rx: Receiver<Extended>,
+    write: &Arc<TokioMutex<OwnedWriteHalf>>,
+    torrent: &Arc<Torrent>,
+    peer: &Arc<TokioMutex<ExtPeer>>,
+) -> JoinHandle<()> {
+    let write = Arc::clone(write);
+    let torrent = Arc::clone(torrent);
+    let peer = Arc::clone(peer);
+
+    task::spawn(async move {
+        while let Ok(msg) = rx.recv().await {
+            let replies = {
+                let mut p = peer.lock().await;
+                torrent.extensions.dispatch(&mut p, &msg)
+            };
+            for r in replies {
+                let w;
+                {
+                    let mut strm = write.lock().await;
+                    w = strm.write_all(&r).await;
+                }
+                if w.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,81 @@
+// bep 6 fast extension, have all/none, suggest, reject and allowed fast
+#![allow(dead_code)]
+
+use super::msg::{bytes::*, structs::*};
+
+use crate::field::ByteField;
+
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use sha1::{Digest, Sha1};
+use tokio::task;
+
+// pieces in the allowed fast set given to each peer
+pub const ALLOWED_FAST_K: usize = 10;
+
+// the canonical allowed fast set for a peer's ip, only defined for ipv4
+pub fn allowed_fast_set(ip: IpAddr, info_hash: &[u8; 20], num_pieces: usize, k: usize) -> Vec<u32> {
+    let ip = match ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => v4,
+            None => return vec![],
+        },
+    };
+    let k = k.min(num_pieces);
+
+    // peers in the same /24 share a set
+    let masked = u32::from(ip) & 0xffff_ff00;
+    let mut x = masked.to_be_bytes().to_vec();
+    x.extend_from_slice(info_hash);
+
+    let mut set = vec![];
+    while set.len() < k {
+        x = Sha1::digest(&x).to_vec();
+        for chunk in x.chunks_exact(4) {
+            if set.len() >= k {
+                break;
+            }
+            let y = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let index = y % num_pieces as u32;
+            if !set.contains(&index) {
+                set.push(index);
+            }
+        }
+    }
+    set
+}
+
+// what we have, sent right after the handshake. with the fast extension
+// seeds and empty peers send have all or have none instead of a bitfield
+pub fn have_msg(field: &Arc<Mutex<ByteField>>, fast: bool) -> Vec<u8> {
+    let (full, bits) = task::block_in_place(|| {
+        let f = field.lock().unwrap();
+        (f.is_full(), f.to_bitfield())
+    });
+    let empty = bits.iter().all(|b| *b == 0);
+
+    if fast && full {
+        Header::new(HAVE_ALL).as_bytes()
+    } else if fast && empty {
+        Header::new(HAVE_NONE).as_bytes()
+    } else if empty {
+        // a bitfield is optional without the fast extension
+        vec![]
+    } else {
+        Bitfield::new(bits).as_bytes()
+    }
+}
+
+// allowed fast messages for a peer we may later choke
+pub fn allowed_fast_msgs(allowed: &HashSet<u32>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for index in allowed {
+        bytes.extend(PieceIndex::new(ALLOWED_FAST, *index).as_bytes());
+    }
+    bytes
+}
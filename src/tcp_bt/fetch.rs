@@ -2,171 +2,368 @@
 #![allow(dead_code)]
 
 use super::{
-    msg::{bytes::*, structs::*, SUBPIECE_LEN},
+    ext::{spawn_ext_handler, ExtPeer},
+    msg::{structs::*, Message, SUBPIECE_LEN},
     parse::Parser,
+    seed::serve_requests,
+    state::{choke_msg, spawn_state_handler, update_interest},
     Connector,
 };
 use crate::{
     field::{constant::*, ByteField},
-    tcp_bt::{parse::ParseItem, seed::fulfill_req},
+    tcp_bt::parse::ParseItem,
     torrent::Torrent,
 };
 
 use std::{
+    collections::HashSet,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc, Mutex,
     },
-    usize, vec,
+    time::{Duration, Instant},
+    vec,
 };
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::tcp::{OwnedReadHalf, OwnedWriteHalf},
-    sync::Mutex as TokioMutex,
+    sync::{broadcast::error::RecvError, Mutex as TokioMutex},
     task::{self, JoinHandle},
+    time,
 };
 
-async fn request_piece(
-    write: &Arc<TokioMutex<OwnedWriteHalf>>,
-    torrent: &Arc<Torrent>,
-    index: u32,
-) -> Option<usize> {
-    let mut request = Request {
-        head: Header {
-            len: 13_u32.to_be(),
-            byte: REQUEST,
-        },
-        index: index.to_be(),
-        plen: SUBPIECE_LEN.to_be(),
-        ..Request::default()
-    };
+// how long to wait for blocks or haves before looking for more to request
+const LISTEN_TIME: Duration = Duration::from_secs(1);
+// blocks requested from a peer before its rate is known
+const INITIAL_DEPTH: usize = 4;
+const MIN_DEPTH: usize = 2;
+// the queue holds about this long of blocks at the peer's measured rate
+const QUEUE_TIME: f64 = 3.0;
+// how often the rate is measured
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+// a peer sending none of the blocks we asked for this long is given up on
+const SNUB_TIME: Duration = Duration::from_secs(60);
 
-    let remainder;
-    if index as usize == torrent.num_pieces - 1 {
-        remainder = torrent.file_len % torrent.piece_len;
+// lengths of the blocks in a piece, the last piece is whatever is left
+fn block_lens(torrent: &Torrent, index: usize) -> Vec<u32> {
+    let len = if index == torrent.num_pieces - 1 {
+        torrent.file_len - index * torrent.piece_len
     } else {
-        remainder = torrent.piece_len;
+        torrent.piece_len
+    };
+    let block = SUBPIECE_LEN as usize;
+    (0..len.div_ceil(block))
+        .map(|i| block.min(len - i * block) as u32)
+        .collect()
+}
+
+// a piece being downloaded, shared by every connection so its blocks can
+// come from several peers and outlive the connection that started it
+pub struct Download {
+    lens: Vec<u32>,
+    blocks: Vec<Option<Piece>>,
+    // connections each block is requested from
+    requests: Vec<u32>,
+}
+
+impl Download {
+    fn new(torrent: &Torrent, index: usize) -> Self {
+        let lens = block_lens(torrent, index);
+        Self {
+            blocks: lens.iter().map(|_| None).collect(),
+            requests: vec![0; lens.len()],
+            lens,
+        }
+    }
+
+    // a block nobody has asked for yet
+    fn unrequested(&self) -> Option<usize> {
+        (0..self.blocks.len()).find(|b| self.blocks[*b].is_none() && self.requests[*b] == 0)
+    }
+
+    // marks a block requested once more
+    fn request(&mut self, index: usize, block: usize) -> Request {
+        self.requests[block] += 1;
+        Request::new(index as u32, block as u32 * SUBPIECE_LEN, self.lens[block])
+    }
+
+    fn is_complete(&self) -> bool {
+        self.blocks.iter().all(Option::is_some)
     }
 
-    let mut num_subpieces = remainder / SUBPIECE_LEN as usize;
-    for i in 0..num_subpieces {
-        request.offset = u32::to_be((i as u32) * SUBPIECE_LEN);
-        let req_u8 = bincode::serialize(&request).unwrap();
+    fn is_untouched(&self) -> bool {
+        self.blocks.iter().all(Option::is_none) && self.requests.iter().all(|r| *r == 0)
+    }
 
-        let w;
-        {
-            let mut strm = write.lock().await;
-            w = strm.write_all(&req_u8).await;
+    fn received(self) -> Vec<Piece> {
+        self.blocks.into_iter().flatten().collect()
+    }
+}
+
+// bytes per second received from the peer, sets how many blocks are kept requested
+struct Rate {
+    bytes: usize,
+    since: Instant,
+    rate: f64,
+}
+
+impl Rate {
+    fn new() -> Self {
+        Self {
+            bytes: 0,
+            since: Instant::now(),
+            rate: 0.0,
         }
-        w.ok()?;
     }
 
-    if remainder % SUBPIECE_LEN as usize > 0 {
-        let last_plen = remainder % SUBPIECE_LEN as usize;
-        request.plen = u32::to_be(last_plen as u32);
-        request.offset = u32::to_be(num_subpieces as u32 * SUBPIECE_LEN);
-        let req_u8 = bincode::serialize(&request).unwrap();
-        let w;
-        {
-            let mut strm = write.lock().await;
-            w = strm.write_all(&req_u8).await;
-        }
-        w.ok()?;
-        num_subpieces += 1;
+    fn add(&mut self, bytes: usize) {
+        self.bytes += bytes;
     }
 
-    Some(num_subpieces)
+    // queue depth for the rate, none until a full interval has passed
+    fn depth(&mut self, max: usize) -> Option<usize> {
+        let elapsed = self.since.elapsed();
+        if elapsed < RATE_INTERVAL {
+            return None;
+        }
+        let current = self.bytes as f64 / elapsed.as_secs_f64();
+        // smoothed so a single slow second doesn't drain the queue
+        self.rate = if self.rate == 0.0 {
+            current
+        } else {
+            self.rate * 0.7 + current * 0.3
+        };
+        self.bytes = 0;
+        self.since = Instant::now();
+        let blocks = (self.rate * QUEUE_TIME / SUBPIECE_LEN as f64).ceil() as usize;
+        Some(blocks.clamp(MIN_DEPTH, max.max(MIN_DEPTH)))
+    }
 }
 
-async fn read_piece(
-    read: &Arc<TokioMutex<OwnedReadHalf>>,
-    write: &Arc<TokioMutex<OwnedWriteHalf>>,
-    parser: &Arc<Parser>,
-    torrent: &Arc<Torrent>,
-    field: &Arc<Mutex<ByteField>>,
-    connector: &Arc<Connector>,
-    count: &Arc<AtomicU32>,
-    num_subpieces: usize,
-) -> Option<()> {
-    let subfield = ByteField {
-        arr: vec![EMPTY; num_subpieces],
-    };
+// what the fetcher asks this peer for next
+enum Next {
+    Request(Request),
+    // the peer has none of the blocks we still need, for now
+    Wait,
+    Done,
+}
 
-    let am_subfield = Arc::new(Mutex::new(subfield));
-    let (byte_tx, byte_rx) = async_channel::unbounded();
-    let (req_tx, req_rx) = async_channel::unbounded();
+// what a connection has under way
+#[derive(Default)]
+struct Queue {
+    // pieces this connection picked and is responsible for finishing
+    mine: Vec<usize>,
+    // requested blocks not received yet, by piece index, offset and length
+    outstanding: Vec<(u32, u32, u32)>,
+    // pieces the peer rejected or sent a bad block of, left to other connections
+    refused: HashSet<u32>,
+}
 
-    let read = Arc::clone(read);
-    let write = Arc::clone(write);
+impl Queue {
+    fn take(&mut self, index: u32, offset: u32) -> Option<(u32, u32, u32)> {
+        let i = self
+            .outstanding
+            .iter()
+            .position(|(p, o, _)| *p == index && *o == offset)?;
+        Some(self.outstanding.swap_remove(i))
+    }
 
-    let torrent = Arc::clone(torrent);
-    let field = Arc::clone(field);
-    let connector = Arc::clone(connector);
-    let count = Arc::clone(count);
-    let subf = Arc::clone(&am_subfield);
+    // refused pieces of ours with nothing left requested, no longer ours
+    fn give_up(&mut self) -> Vec<usize> {
+        let (idle, kept) = self.mine.iter().partition(|p| {
+            let p = **p as u32;
+            self.refused.contains(&p) && !self.outstanding.iter().any(|(i, _, _)| *i == p)
+        });
+        self.mine = kept;
+        idle
+    }
+}
+
+// next block for the peer: the rest of our pieces, then a newly picked piece,
+// then in endgame blocks other peers are already fetching
+fn next_request(
+    torrent: &Torrent,
+    field: &Arc<Mutex<ByteField>>,
+    connector: &Connector,
+    queue: &mut Queue,
+    peer: &ExtPeer,
+) -> Next {
+    let pieces = &peer.pieces;
+    let allowed = &peer.peer_allowed_fast;
+    let choked = peer.choke.peer_choking;
+    task::block_in_place(|| {
+        // critical section, the field is always locked before the downloads
+        let mut pf = field.lock().unwrap();
+        if connector.brk.load(Ordering::Relaxed) || pf.is_full() {
+            return Next::Done;
+        }
+        let mut downloads = connector.downloads.lock().unwrap();
+        // pieces finished since are no longer ours
+        queue.mine.retain(|p| downloads.contains_key(p));
 
-    let seeder: JoinHandle<Option<()>> = task::spawn(async move {
         loop {
-            let req = match req_rx.recv().await {
-                Ok(r) => r,
-                Err(_) => return Some(()),
+            for p in &queue.mine {
+                let i = *p as u32;
+                if choked && !allowed.contains(&i) || queue.refused.contains(&i) {
+                    continue;
+                }
+                let d = downloads.get_mut(p).unwrap();
+                if let Some(b) = d.unrequested() {
+                    return Next::Request(d.request(*p, b));
+                }
+            }
+
+            let wanted = |i: &usize| {
+                pf.arr.get(*i) == Some(&EMPTY)
+                    && pieces.has(*i)
+                    && !queue.refused.contains(&(*i as u32))
+            };
+            let pick = if choked {
+                // only what the peer allows while choking us
+                allowed.iter().map(|i| *i as usize).find(wanted)
+            } else {
+                // the peer's most recent suggestion first, then the torrent's picker
+                let suggestion = peer
+                    .suggested
+                    .iter()
+                    .rev()
+                    .map(|i| *i as usize)
+                    .find(wanted);
+                suggestion.or_else(|| {
+                    // abandoned pieces with blocks kept
+                    let partial: Vec<usize> = downloads
+                        .keys()
+                        .copied()
+                        .filter(|i| pf.arr[*i] == EMPTY)
+                        .collect();
+                    let avail = connector.avail.lock().unwrap();
+                    torrent
+                        .picker
+                        .pick(&pf, pieces, &avail, &partial, &queue.refused)
+                })
             };
-            match fulfill_req(&write, &torrent, &field, &count, &req).await {
-                Some(_) => continue,
-                None => return None,
+            match pick {
+                Some(p) => {
+                    pf.arr[p] = IN_PROGRESS;
+                    downloads
+                        .entry(p)
+                        .or_insert_with(|| Download::new(torrent, p));
+                    queue.mine.push(p);
+                }
+                None => break,
             }
         }
-    });
 
-    let reader = task::spawn(async move {
-        let mut buf = vec![0; 65536];
-        loop {
-            if connector.brk.load(Ordering::Relaxed) {
-                return None;
-            }
-            if task::block_in_place(|| {
-                let subf = subf.lock().unwrap();
-                if subf.is_full() {
-                    return None;
-                }
-                return Some(());
-            })
-            .is_none()
-            {
-                break;
+        // endgame, every piece left is under way. a slow peer shouldn't hold
+        // up the end, so blocks others are waiting on are asked for here too
+        if choked || pf.arr.contains(&EMPTY) {
+            return Next::Wait;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        for (p, d) in downloads.iter() {
+            if !pieces.has(*p) || queue.refused.contains(&(*p as u32)) {
+                continue;
             }
-            
-            let r;
-            {
-                let mut strm = read.lock().await;
-                r = strm.read(&mut buf).await;
+            for b in 0..d.blocks.len() {
+                let offset = b as u32 * SUBPIECE_LEN;
+                let ours = queue
+                    .outstanding
+                    .iter()
+                    .any(|(i, o, _)| *i == *p as u32 && *o == offset);
+                if d.blocks[b].is_some() || ours {
+                    continue;
+                }
+                // the blocks fewest peers are fetching first
+                if best.is_none_or(|(bp, bb)| d.requests[b] < downloads[&bp].requests[bb]) {
+                    best = Some((*p, b));
+                }
             }
-            let bytes = r.ok()?;
+        }
+        match best {
+            Some((p, b)) => Next::Request(downloads.get_mut(&p).unwrap().request(p, b)),
+            None => Next::Wait,
+        }
+    })
+}
 
-            if byte_tx.send(buf[..bytes].to_vec()).await.is_err() {
-                break;
+// stores a requested block, its offset and length already checked against
+// the request. returns the piece if it is now complete and whether other
+// peers were asked for the block too
+fn receive(connector: &Connector, piece: Piece) -> (Option<Download>, bool) {
+    task::block_in_place(|| {
+        let mut downloads = connector.downloads.lock().unwrap();
+        let index = piece.index as usize;
+        let b = (piece.offset / SUBPIECE_LEN) as usize;
+        let d = match downloads.get_mut(&index) {
+            Some(d) if b < d.blocks.len() => d,
+            _ => return (None, false),
+        };
+        d.requests[b] = d.requests[b].saturating_sub(1);
+        // another peer's copy got here first
+        if d.blocks[b].is_some() {
+            return (None, false);
+        }
+        let shared = d.requests[b] > 0;
+        d.blocks[b] = Some(piece);
+        if d.is_complete() {
+            (downloads.remove(&index), shared)
+        } else {
+            (None, shared)
+        }
+    })
+}
+
+// forgets requests that won't be answered
+fn drop_requests(connector: &Connector, requests: &[(u32, u32, u32)]) {
+    task::block_in_place(|| {
+        let mut downloads = connector.downloads.lock().unwrap();
+        for (index, offset, _) in requests {
+            if let Some(d) = downloads.get_mut(&(*index as usize)) {
+                let b = (offset / SUBPIECE_LEN) as usize;
+                if let Some(r) = d.requests.get_mut(b) {
+                    *r = r.saturating_sub(1);
+                }
             }
         }
-        drop(byte_tx);
-        return Some(());
     });
+}
 
-    let item = ParseItem {
-        rx: byte_rx,
-        tx: req_tx,
-        handle: reader,
-        field: Some(Arc::clone(&am_subfield)),
-    };
-
-    parser.tx.send(item).await.unwrap();
-    
-    seeder.await.unwrap()?;
+// gives pieces back to be picked again, their blocks kept for whoever does
+fn release(pieces: &[usize], field: &Arc<Mutex<ByteField>>, connector: &Connector) {
+    task::block_in_place(|| {
+        let mut f = field.lock().unwrap();
+        let mut downloads = connector.downloads.lock().unwrap();
+        for index in pieces {
+            let d = match downloads.get(index) {
+                Some(d) => d,
+                // finished, the hashing threads decide what happens to it
+                None => continue,
+            };
+            if d.is_untouched() {
+                downloads.remove(index);
+            }
+            if f.arr[*index] == IN_PROGRESS {
+                f.arr[*index] = EMPTY;
+            }
+        }
+        connector.piece.notify_all();
+    });
+}
 
-    return Some(());
+// hands a finished piece to the hashing threads
+fn finish(download: Download, connector: &Connector) {
+    let blocks = download.received();
+    task::block_in_place(|| {
+        let mut q = connector.hasher.queue.lock().unwrap();
+        q.push_back(blocks);
+        connector.hasher.loops.notify_one();
+    });
 }
 
-// represents a single connection to a peer, continously fetches subpieces
+// represents a single connection to a peer, keeps blocks from several pieces
+// requested at once then serves the peer's requests once nothing is left
+#[allow(clippy::too_many_arguments)]
 pub async fn torrent_fetcher(
     read: &Arc<TokioMutex<OwnedReadHalf>>,
     write: &Arc<TokioMutex<OwnedWriteHalf>>,
@@ -175,65 +372,208 @@ pub async fn torrent_fetcher(
     field: &Arc<Mutex<ByteField>>,
     connector: &Arc<Connector>,
     count: &Arc<AtomicU32>,
-) -> Vec<usize> {
-    let mut idxs = vec![];
-    // get pieces
-    loop {
-        let mut nums = vec![];
-        // peers reject if you request more than 1 piece
-        for _ in 0..1_usize {
-            // pick a piece
-            let piece_idx = match task::block_in_place(move || {
-                // critical section
-                let mut pf = connector
-                    .piece
-                    .wait_while(field.lock().unwrap(), |f| {
-                        if connector.brk.load(Ordering::Relaxed) {
-                            return false;
-                        }
-                        if f.is_full() {
-                            return false;
-                        }
-                        f.get_empty().is_none()
-                    })
-                    .unwrap();
-                if let Some(p) = pf.get_empty() {
-                    pf.arr[p] = IN_PROGRESS;
-                    return Some(p);
-                } else {
-                    return None;
+    ext_peer: &Arc<TokioMutex<ExtPeer>>,
+) {
+    let (byte_tx, byte_rx) = async_channel::unbounded();
+    let (req_tx, req_rx) = async_channel::unbounded();
+    let (ext_tx, ext_rx) = async_channel::unbounded();
+    spawn_ext_handler(ext_rx, write, torrent, ext_peer);
+    let (state_tx, state_rx) = async_channel::unbounded();
+    spawn_state_handler(state_rx, write, torrent, field, connector, ext_peer);
+    let (block_tx, block_rx) = async_channel::unbounded();
+    let mut arrived = connector.arrived.subscribe();
+    let fast = ext_peer.lock().await.fast;
+
+    let seeder: JoinHandle<()> = {
+        let write = Arc::clone(write);
+        let torrent = Arc::clone(torrent);
+        let field = Arc::clone(field);
+        let count = Arc::clone(count);
+        let ext_peer = Arc::clone(ext_peer);
+        task::spawn(async move {
+            serve_requests(req_rx, &write, &torrent, &field, &count, &ext_peer).await;
+        })
+    };
+
+    let reader = {
+        let read = Arc::clone(read);
+        let connector = Arc::clone(connector);
+        task::spawn(async move {
+            let mut buf = vec![0; 65536];
+            loop {
+                let r;
+                {
+                    let mut strm = read.lock().await;
+                    r = strm.read(&mut buf).await;
                 }
-            }) {
-                Some(p) => p,
-                None => return idxs,
-            };
-            idxs.push(piece_idx);
+                let bytes = r.ok()?;
+                if connector.brk.load(Ordering::Relaxed) || bytes == 0 {
+                    break;
+                }
+                if byte_tx.send(buf[..bytes].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+            drop(byte_tx);
+            Some(())
+        })
+    };
 
-            // fetch piece
-            let num = request_piece(write, torrent, piece_idx as u32).await;
+    let item = ParseItem {
+        rx: byte_rx,
+        tx: req_tx,
+        ext: ext_tx,
+        state: state_tx,
+        handle: reader,
+        blocks: Some(block_tx),
+    };
+    if parser.tx.send(item).await.is_err() {
+        return;
+    }
 
-            if let Some(n) = num {
-                nums.push(n)
-            } else {
-                return idxs;
+    let mut queue = Queue::default();
+    let mut depth = INITIAL_DEPTH;
+    let mut rate = Rate::new();
+    let mut last_block = Instant::now();
+
+    loop {
+        // the peer stays locked while the queue is topped up, so the picker
+        // reads its pieces in place
+        let peer = ext_peer.lock().await;
+        // never more than the peer says it queues, a reqq of 0 is ignored
+        let max = match peer.handshake.as_ref().and_then(|h| h.reqq) {
+            Some(r) if r > 0 => torrent.queue_depth.min(r as usize),
+            _ => torrent.queue_depth,
+        };
+        if let Some(d) = rate.depth(max) {
+            depth = d;
+        }
+        depth = depth.min(max);
+
+        // top the queue up
+        let mut requests = vec![];
+        let mut done = false;
+        while queue.outstanding.len() < depth {
+            match next_request(torrent, field, connector, &mut queue, &peer) {
+                Next::Request(req) => {
+                    queue.outstanding.push((req.index, req.offset, req.plen));
+                    requests.extend(req.as_bytes());
+                }
+                Next::Wait => break,
+                Next::Done => {
+                    done = true;
+                    break;
+                }
+            }
+        }
+        drop(peer);
+        if !requests.is_empty() && write.lock().await.write_all(&requests).await.is_err() {
+            break;
+        }
+        if done && queue.outstanding.is_empty() {
+            break;
+        }
+        if queue.outstanding.is_empty() {
+            // pieces finished from other peers may leave nothing we want here
+            let interest = update_interest(&mut *ext_peer.lock().await, field);
+            if !interest.is_empty() && write.lock().await.write_all(&interest).await.is_err() {
+                break;
             }
+            last_block = Instant::now();
+        } else if last_block.elapsed() > SNUB_TIME {
+            break;
         }
-        for num_subpieces in nums {
-            if read_piece(
-                &read,
-                &write,
-                parser,
-                torrent,
-                field,
-                connector,
-                count,
-                num_subpieces,
-            )
-            .await
-            .is_none()
-            {
-                return idxs;
+
+        let msg = tokio::select! {
+            m = block_rx.recv() => match m {
+                Ok(m) => m,
+                // the connection closed
+                Err(_) => break,
+            },
+            a = arrived.recv() => {
+                // a block we also asked for came from another peer
+                let (index, offset) = match a {
+                    Ok(a) => a,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some((i, o, l)) = queue.take(index, offset) {
+                    drop_requests(connector, &[(i, o, l)]);
+                    let cancel = Cancel::new(&Request::new(i, o, l)).as_bytes();
+                    if write.lock().await.write_all(&cancel).await.is_err() {
+                        break;
+                    }
+                }
+                continue;
+            },
+            // nothing arrived, the peer may have new pieces
+            _ = time::sleep(LISTEN_TIME) => continue,
+        };
+        match msg {
+            Message::Piece(piece) => {
+                let req = match queue.take(piece.index, piece.offset) {
+                    Some(r) => r,
+                    // not requested, or requested and cancelled since
+                    None => continue,
+                };
+                // a block of the wrong length would take the real one's
+                // slot, it is dropped and the piece left to others
+                if piece.data.len() != req.2 as usize {
+                    drop_requests(connector, &[req]);
+                    queue.refused.insert(piece.index);
+                    release(&queue.give_up(), field, connector);
+                    continue;
+                }
+                rate.add(piece.data.len());
+                last_block = Instant::now();
+
+                let key = (piece.index, piece.offset);
+                let (complete, shared) = receive(connector, piece);
+                if shared {
+                    let _ = connector.arrived.send(key);
+                }
+                if let Some(d) = complete {
+                    finish(d, connector);
+                }
+                release(&queue.give_up(), field, connector);
             }
+            Message::Reject(r) => {
+                // the rejected block goes to other connections, the rest of
+                // the piece stays queued with the peer
+                if let Some(req) = queue.take(r.index, r.offset) {
+                    drop_requests(connector, &[req]);
+                }
+                queue.refused.insert(r.index);
+                release(&queue.give_up(), field, connector);
+            }
+            m @ (Message::Choke(_) | Message::Unchoke(_)) => {
+                choke_msg(&mut *ext_peer.lock().await, &m);
+                if let Message::Unchoke(_) = m {
+                    // an unchoke starts over, the peer may serve them now
+                    queue.refused.clear();
+                    continue;
+                }
+                // without the fast extension a choke drops our requests,
+                // with it the peer rejects each one it won't serve
+                if !fast {
+                    drop_requests(connector, &queue.outstanding);
+                    queue.outstanding.clear();
+                    release(&queue.mine, field, connector);
+                    queue.mine.clear();
+                }
+            }
+            _ => {}
         }
     }
+
+    drop_requests(connector, &queue.outstanding);
+    release(&queue.mine, field, connector);
+    drop(block_rx);
+    // nothing left to want once the torrent is complete
+    let interest = update_interest(&mut *ext_peer.lock().await, field);
+    if !interest.is_empty() {
+        let _ = write.lock().await.write_all(&interest).await;
+    }
+    // keep serving the peer's requests on the same session
+    let _ = seeder.await;
 }
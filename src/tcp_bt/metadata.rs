@@ -0,0 +1,252 @@
+// fetches the info dict from peers for magnet links, bep 9 ut_metadata
+#![allow(dead_code)]
+
+use super::{
+    ext::{ExtHandshake, EXT_HANDSHAKE},
+    msg::{
+        bytes::{EXTENDED, HAVE_NONE},
+        structs::{Extended, Header},
+    },
+    send_handshake,
+};
+
+use crate::{
+    bencode::{self, de::from_item, decode::Decoder},
+    dht::{self, DhtConfig},
+    magnet::Magnet,
+    tracker::{announce, get_info_hash, get_info_hash_v2, make_addr},
+};
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task, time,
+};
+
+pub const NAME: &str = "ut_metadata";
+// id we ask peers to use for ut_metadata messages sent to us
+const UT_METADATA: u8 = 1;
+
+// ut_metadata msg_type values
+const REQUEST: i64 = 0;
+const DATA: i64 = 1;
+const REJECT: i64 = 2;
+
+const METADATA_PIECE_LEN: usize = 0x4000;
+const MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+// longest message accepted, enough for the bitfield of a large torrent
+const MAX_MSG_LEN: usize = 1024 * 1024;
+// give up on a peer after this long
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+// peers tried at once
+const MAX_PEERS: usize = 50;
+// nothing listens until the download starts, so no port is announced
+const ANNOUNCE_PORT: u16 = 0;
+
+#[derive(Serialize, Deserialize)]
+struct MetadataMsg {
+    msg_type: i64,
+    piece: i64,
+    total_size: Option<i64>,
+}
+
+// reads one length prefixed message, skipping keep alives
+async fn read_msg(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    loop {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).await.ok()?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 {
+            continue;
+        }
+        if len > MAX_MSG_LEN {
+            return None;
+        }
+        let mut msg = vec![0; len];
+        stream.read_exact(&mut msg).await.ok()?;
+        let id = msg.remove(0);
+        return Some((id, msg));
+    }
+}
+
+async fn send_ext(stream: &mut TcpStream, ext_id: u8, payload: Vec<u8>) -> Option<()> {
+    let msg = Extended::new(ext_id, payload).as_bytes();
+    stream.write_all(&msg).await.ok()
+}
+
+// handshakes with a single peer and downloads every metadata piece from it
+async fn peer_metadata(
+    addr: SocketAddr,
+    info_hash: [u8; 20],
+    info_hash_v2: Option<[u8; 32]>,
+    peer_id: [u8; 20],
+) -> Option<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr).await.ok()?;
+
+    // same handshake and checks as a download connection
+    let theirs = send_handshake(&mut stream, info_hash, peer_id).await?;
+    if !theirs.extensions() {
+        return None;
+    }
+    // the fast extension requires saying what we have, which is nothing
+    if theirs.fast() {
+        stream
+            .write_all(&Header::new(HAVE_NONE).as_bytes())
+            .await
+            .ok()?;
+    }
+
+    let mut ours = ExtHandshake::default();
+    ours.m.insert(NAME.to_string(), UT_METADATA as i64);
+    send_ext(&mut stream, EXT_HANDSHAKE, bencode::to_bytes(&ours).ok()?).await?;
+
+    let mut size = 0;
+    let mut pieces: Vec<Option<Vec<u8>>> = vec![];
+    loop {
+        let (id, msg) = read_msg(&mut stream).await?;
+        if id != EXTENDED || msg.is_empty() {
+            continue;
+        }
+        let payload = &msg[1..];
+
+        if msg[0] == EXT_HANDSHAKE {
+            let ext = ExtHandshake::parse(payload)?;
+            let their_id = ext.id(NAME)?;
+            size = match ext.metadata_size {
+                Some(s) if s > 0 && s as usize <= MAX_METADATA_SIZE => s as usize,
+                _ => return None,
+            };
+            pieces = vec![None; size.div_ceil(METADATA_PIECE_LEN)];
+
+            // request every piece up front
+            for i in 0..pieces.len() {
+                let req = MetadataMsg {
+                    msg_type: REQUEST,
+                    piece: i as i64,
+                    total_size: None,
+                };
+                send_ext(&mut stream, their_id, bencode::to_bytes(&req).ok()?).await?;
+            }
+        } else if msg[0] == UT_METADATA && !pieces.is_empty() {
+            // dict header followed by the raw piece
+            let mut decoder = Decoder::new(payload);
+            let item = decoder.parse_item().ok()?;
+            let data = &payload[decoder.position()..];
+            let resp: MetadataMsg = from_item(item).ok()?;
+            if resp.msg_type != DATA {
+                return None;
+            }
+
+            let index = resp.piece as usize;
+            if resp.piece < 0 || index >= pieces.len() {
+                return None;
+            }
+            let expected = if index == pieces.len() - 1 {
+                size - index * METADATA_PIECE_LEN
+            } else {
+                METADATA_PIECE_LEN
+            };
+            if data.len() != expected {
+                return None;
+            }
+            pieces[index] = Some(data.to_vec());
+
+            if pieces.iter().all(Option::is_some) {
+                let info = pieces.into_iter().flatten().flatten().collect::<Vec<u8>>();
+                if get_info_hash(&info) != info_hash {
+                    return None;
+                }
+                // a btmh in the magnet has to match too
+                if info_hash_v2.is_some_and(|h| get_info_hash_v2(&info) != h) {
+                    return None;
+                }
+                return Some(info);
+            }
+        }
+    }
+}
+
+// gathers peers from the magnet's trackers and x.pe entries, then downloads
+// the info dict from the first peer that can verifiably provide it
+pub async fn fetch_metadata(magnet: &Magnet, peer_id: [u8; 20]) -> Result<Vec<u8>, String> {
+    let info_hash = magnet.info_hash;
+    let info_hash_v2 = magnet.info_hash_v2;
+
+    let mut addrs = magnet.peer_addrs().await;
+    for url in &magnet.trackers {
+        let addr = match make_addr(url) {
+            Ok(a) => a,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        match announce(addr, info_hash, peer_id, ANNOUNCE_PORT).await {
+            Ok(peers) => addrs.extend(
+                peers
+                    .iter()
+                    .map(|p| SocketAddr::new(IpAddr::from(Ipv4Addr::from(p.ip)), p.port)),
+            ),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    // fall back to the dht when trackers and x.pe gave nothing
+    if addrs.is_empty() {
+        let config = DhtConfig::default();
+        match dht::start(&config).await {
+            Ok(node) => {
+                addrs.extend(node.find_peers(info_hash).await);
+                node.shutdown();
+                if let Some(path) = &config.state {
+                    if let Err(e) = node.save(path).await {
+                        eprintln!("dht {}", e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("dht {}", e),
+        }
+    }
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        return Err("no peers found for magnet".to_string());
+    }
+
+    for chunk in addrs.chunks(MAX_PEERS) {
+        let (tx, rx) = async_channel::unbounded();
+        let mut handles = vec![];
+        for addr in chunk {
+            let addr = *addr;
+            let tx = tx.clone();
+            handles.push(task::spawn(async move {
+                let info = time::timeout(
+                    PEER_TIMEOUT,
+                    peer_metadata(addr, info_hash, info_hash_v2, peer_id),
+                )
+                .await
+                .ok()
+                .flatten();
+                let _ = tx.send(info).await;
+            }));
+        }
+        drop(tx);
+
+        // first verified info dict wins
+        while let Ok(info) = rx.recv().await {
+            if let Some(info) = info {
+                for h in handles {
+                    h.abort();
+                }
+                return Ok(info);
+            }
+        }
+    }
+
+    Err(format!("no peer sent metadata ({} tried)", addrs.len()))
+}
@@ -2,19 +2,29 @@
 #![allow(dead_code)]
 
 pub mod connect;
+pub mod ext;
+pub mod fast;
 pub mod fetch;
+pub mod metadata;
 pub mod msg;
 pub mod parse;
+pub mod pex;
+pub mod picker;
 pub mod seed;
+pub mod state;
 
 use crate::{
+    dht,
     field::{constant::*, ByteField},
     file::resume_torrent,
     hash::{spawn_hash_write, Hasher},
+    lsd,
     tcp_bt::{
         connect::{spawn_connector_task, Connector},
-        msg::{bytes::*, structs::*, SUBPIECE_LEN},
+        ext::Extension,
+        msg::{structs::*, SUBPIECE_LEN},
         parse::{spawn_parsers, Parser},
+        pex::Pex,
         seed::{spawn_listener, Peer},
     },
     torrent::Torrent,
@@ -22,56 +32,117 @@ use crate::{
 };
 
 use std::{
+    collections::HashSet,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc, Mutex,
     },
+    time::Duration,
 };
 
 use tokio::{
-    io::AsyncWriteExt,
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     runtime::Handle,
     task::{self, JoinHandle},
     time,
 };
 
-use num_cpus;
+// peers that don't complete the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
+// exchanges handshakes, returning the peer's once it is for the same
+// torrent and from someone other than ourselves
 pub async fn send_handshake(
     stream: &mut TcpStream,
     info_hash: [u8; 20],
     peer_id: [u8; 20],
-) -> Option<()> {
+) -> Option<Handshake> {
+    time::timeout(
+        HANDSHAKE_TIMEOUT,
+        exchange_handshake(stream, info_hash, peer_id),
+    )
+    .await
+    .ok()?
+}
+
+async fn exchange_handshake(
+    stream: &mut TcpStream,
+    info_hash: [u8; 20],
+    peer_id: [u8; 20],
+) -> Option<Handshake> {
     // make handshake
     let handshake = Handshake {
         info_hash,
         peer_id,
         ..Handshake::default()
     };
-    let interest = Header {
-        len: 1_u32.to_be(),
-        byte: INTEREST,
-    };
-    let mut handshake_u8 = bincode::serialize(&handshake).unwrap();
+    let handshake_u8 = bincode::serialize(&handshake).unwrap();
 
     // send handshake
-    handshake_u8.append(&mut bincode::serialize(&interest).unwrap());
     stream.write_all(&handshake_u8).await.ok()?;
 
-    // receive handshake
-    let mut buf: Vec<u8> = vec![0; 8192];
-    stream.peek(&mut buf).await.ok()?;
-    Some(())
+    // receive handshake, parse checks the protocol string
+    let mut buf: Vec<u8> = vec![0; 68];
+    stream.read_exact(&mut buf).await.ok()?;
+    let theirs = Handshake::parse(&mut buf)?;
+    if theirs.info_hash != info_hash {
+        return None;
+    }
+    // e.g. our own address came back from a tracker or pex
+    if theirs.peer_id == peer_id {
+        return None;
+    }
+
+    Some(theirs)
 }
 
 // makes connections to peers and downloads the torrent files
 impl Torrent {
-    pub async fn start(self) {
+    pub async fn start(mut self) {
+        // peers learned over pex, the dht and lsd arrive on peer_rx
+        let (peer_tx, peer_rx) = async_channel::unbounded();
+        let pex = Arc::new(Pex::new(peer_tx.clone()));
+        if self.pex {
+            self.extensions
+                .register(Arc::clone(&pex) as Arc<dyn Extension>);
+        }
+
         let torrent = Arc::new(self);
         // parse torrent file
-        let addr = get_addr(&torrent.tree).unwrap();
+        let addr = match get_addr(&torrent.meta) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        };
+        let node = match &torrent.dht {
+            Some(config) => match dht::start(config).await {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    eprintln!("dht {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let local = match &torrent.lsd {
+            Some(config) => match lsd::start(config).await {
+                Ok(l) => Some(l),
+                Err(e) => {
+                    eprintln!("lsd {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let no_discovery = node.is_none() && local.is_none();
+        if addr.is_none() && torrent.peers.is_empty() && no_discovery {
+            eprintln!("no trackers, peers, dht or lsd to download from");
+            return;
+        }
 
         // local tracker testing
         // use std::net::ToSocketAddrs;
@@ -82,10 +153,9 @@ impl Torrent {
         let field: Arc<Mutex<ByteField>> = Arc::new(Mutex::new(ByteField {
             arr: vec![EMPTY; torrent.num_pieces],
         }));
-        let connector = Arc::new(Connector::new());
-
         // spawn hashing thread pool
         let hasher = Arc::new(Hasher::new());
+        let connector = Arc::new(Connector::new(pex, torrent.num_pieces, &hasher));
         let handle = Handle::current();
         let threads = num_cpus::get();
         let hasher_handles = spawn_hash_write(
@@ -102,10 +172,11 @@ impl Torrent {
 
         // start parser thread pool
         let parser = Arc::new(Parser::new());
-        let parser_handles = spawn_parsers(&parser, &hasher, handle.clone(), 50);
+        let parser_handles = spawn_parsers(&parser, handle.clone(), 50);
 
         let scount = Arc::new(AtomicU32::new(0));
-        let mut conn_handles: Vec<JoinHandle<()>> = vec![];
+        // outgoing connections and the address each was made to
+        let mut conn_handles: Vec<(SocketAddr, JoinHandle<()>)> = vec![];
 
         let listener = TcpListener::bind(("0.0.0.0", 0)).await.unwrap();
         let port = listener.local_addr().unwrap().port();
@@ -115,10 +186,33 @@ impl Torrent {
         let tor = Arc::clone(&torrent);
         let num_subpieces = tor.piece_len / SUBPIECE_LEN as usize;
 
+        if let Some(local) = &local {
+            local.add(tor.info_hash, port, peer_tx.clone()).await;
+        }
+
+        // the running dht announce, at most one at a time
+        let mut dht_handle: Option<JoinHandle<()>> = None;
+
+        // peers known before announcing
+        for peer in &tor.peers {
+            conn_handles.push((
+                *peer,
+                spawn_connector_task(
+                    Peer::Addr(*peer),
+                    &parser,
+                    &torrent,
+                    &field,
+                    &connector,
+                    &scount,
+                )
+                .await,
+            ));
+        }
+
         // main loop control
         let mut seeded = 0_usize;
         let mut counter = 0_usize;
-        const ANNOUNCE_INTERVAL: usize = 60 / LOOP_SLEEP as usize;
+        const ANNOUNCE_INTERVAL: usize = 60 / LOOP_SLEEP;
         const LOOP_SLEEP: usize = 1;
 
         // shutdown when share ratio >= 1
@@ -135,8 +229,24 @@ impl Torrent {
             print!("progress {}/{}, ", progress, tor.num_pieces);
             println!("seeded {}/{}", seeded, tor.num_pieces);
 
-            if counter % ANNOUNCE_INTERVAL == 0 {
-                let peers = match announce(addr, tor.info_hash, port).await {
+            // addresses with a connection running, ended ones may be tried again
+            conn_handles.retain(|(_, h)| !h.is_finished());
+            let mut known: HashSet<SocketAddr> = conn_handles.iter().map(|(a, _)| *a).collect();
+
+            let announce_now = counter.is_multiple_of(ANNOUNCE_INTERVAL);
+            let dht_idle = dht_handle.as_ref().is_none_or(|h| h.is_finished());
+            if let (Some(node), true, true) = (&node, announce_now, dht_idle) {
+                let node = Arc::clone(node);
+                let peer_tx = peer_tx.clone();
+                let info_hash = tor.info_hash;
+                dht_handle = Some(task::spawn(async move {
+                    for peer in node.announce(info_hash, port).await {
+                        let _ = peer_tx.send(peer).await;
+                    }
+                }));
+            }
+            if let (Some(addr), true) = (addr, announce_now) {
+                let peers = match announce(addr, tor.info_hash, tor.peer_id, port).await {
                     Ok(p) => p,
                     Err(e) => {
                         eprintln!("{}", e);
@@ -149,8 +259,10 @@ impl Torrent {
                         continue;
                     }
                     let addr = SocketAddr::new(IpAddr::from(Ipv4Addr::from(peer.ip)), peer.port);
+                    known.insert(addr);
                     let connector = Arc::clone(&connector);
-                    conn_handles.push(
+                    conn_handles.push((
+                        addr,
                         spawn_connector_task(
                             Peer::Addr(addr),
                             &parser,
@@ -160,15 +272,34 @@ impl Torrent {
                             &scount,
                         )
                         .await,
-                    );
+                    ));
                 }
             }
 
+            // connect to new peers learned over pex, the dht and lsd
+            while let Ok(addr) = peer_rx.try_recv() {
+                if addr.port() == port || !known.insert(addr) {
+                    continue;
+                }
+                conn_handles.push((
+                    addr,
+                    spawn_connector_task(
+                        Peer::Addr(addr),
+                        &parser,
+                        &torrent,
+                        &field,
+                        &connector,
+                        &scount,
+                    )
+                    .await,
+                ));
+            }
+
             counter += 1;
             time::sleep(std::time::Duration::from_secs(LOOP_SLEEP as u64)).await;
 
             seeded = scount.load(Ordering::Relaxed) as usize / num_subpieces;
-            if scount.load(Ordering::Relaxed) as usize % num_subpieces > 0 {
+            if !(scount.load(Ordering::Relaxed) as usize).is_multiple_of(num_subpieces) {
                 seeded += 1;
             }
         }
@@ -176,8 +307,7 @@ impl Torrent {
         // shutdown
         println!("shutting down");
         // break hasher loops
-        hasher.brk.store(true, Ordering::Relaxed);
-        hasher.loops.notify_all();
+        hasher.stop();
         // break connection loops
         connector.brk.store(true, Ordering::Relaxed);
         // break parser loops
@@ -194,5 +324,20 @@ impl Torrent {
         });
         l_handle.abort();
         let _ = l_handle.await;
+        if let Some(local) = local {
+            local.shutdown();
+        }
+        // keep the routing table for the next run
+        if let Some(h) = dht_handle {
+            h.abort();
+        }
+        if let Some(node) = node {
+            node.shutdown();
+            if let Some(path) = torrent.dht.as_ref().and_then(|c| c.state.as_ref()) {
+                if let Err(e) = node.save(path).await {
+                    eprintln!("dht {}", e);
+                }
+            }
+        }
     } // need to abort hanging reads
 }
@@ -12,9 +12,23 @@ pub mod bytes {
     pub const REQUEST: u8 = 6;
     pub const PIECE: u8 = 7;
     pub const CANCEL: u8 = 8;
+    // bep 6 fast extension
+    pub const SUGGEST: u8 = 13;
+    pub const HAVE_ALL: u8 = 14;
+    pub const HAVE_NONE: u8 = 15;
+    pub const REJECT: u8 = 16;
+    pub const ALLOWED_FAST: u8 = 17;
+    pub const EXTENDED: u8 = 20;
     pub const HANDSHAKE: u8 = 0x54;
 }
 
+// reserved handshake byte and bit advertising the bep 10 extension protocol
+pub const EXT_RESERVED_BYTE: usize = 5;
+pub const EXT_RESERVED_BIT: u8 = 0x10;
+// reserved handshake byte and bit advertising the bep 6 fast extension
+pub const FAST_RESERVED_BYTE: usize = 7;
+pub const FAST_RESERVED_BIT: u8 = 0x04;
+
 // takes off top 4 bytes to make u32
 fn parse_u32(msg: &[u8]) -> u32 {
     let mut bytes: [u8; 4] = [0; 4];
@@ -24,7 +38,10 @@ fn parse_u32(msg: &[u8]) -> u32 {
 
 // structs for each type of message
 pub mod structs {
-    use super::{bytes::*, parse_u32};
+    use super::{
+        bytes::*, parse_u32, EXT_RESERVED_BIT, EXT_RESERVED_BYTE, FAST_RESERVED_BIT,
+        FAST_RESERVED_BYTE,
+    };
 
     use serde::Serialize;
     #[derive(Serialize, Debug)]
@@ -41,10 +58,13 @@ pub mod structs {
             let name = b"BitTorrent protocol";
             let mut p: [u8; 19] = [0; 19];
             p.copy_from_slice(&name[0..19]);
+            let mut reserved = [0; 8];
+            reserved[EXT_RESERVED_BYTE] |= EXT_RESERVED_BIT;
+            reserved[FAST_RESERVED_BYTE] |= FAST_RESERVED_BIT;
             Handshake {
                 len: 19,
                 protocol: p,
-                reserved: [0; 8],
+                reserved,
                 info_hash: [0; 20],
                 peer_id: [0; 20],
             }
@@ -62,6 +82,16 @@ pub mod structs {
                 .all(|(a, b)| *a == *b)
         }
 
+        // whether the peer speaks the extension protocol
+        pub fn extensions(&self) -> bool {
+            self.reserved[EXT_RESERVED_BYTE] & EXT_RESERVED_BIT != 0
+        }
+
+        // whether the peer speaks the fast extension
+        pub fn fast(&self) -> bool {
+            self.reserved[FAST_RESERVED_BYTE] & FAST_RESERVED_BIT != 0
+        }
+
         pub fn parse(msg: &mut Vec<u8>) -> Option<Self> {
             if msg.len() < 68 {
                 return None;
@@ -93,7 +123,15 @@ pub mod structs {
 
     impl Header {
         fn test(&self) -> bool {
-            self.byte <= CANCEL || self.byte == HANDSHAKE
+            self.byte <= CANCEL
+                || (SUGGEST..=ALLOWED_FAST).contains(&self.byte)
+                || self.byte == HANDSHAKE
+                || self.byte == EXTENDED
+        }
+
+        // header of a message without a payload, e.g. have all
+        pub fn new(byte: u8) -> Self {
+            Header { len: 1, byte }
         }
 
         pub fn parse(msg: &[u8]) -> Option<Self> {
@@ -186,6 +224,22 @@ pub mod structs {
                 None
             }
         }
+
+        pub fn new(data: Vec<u8>) -> Self {
+            Bitfield {
+                head: Header {
+                    len: data.len() as u32 + 1,
+                    byte: BITFIELD,
+                },
+                data,
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.extend_from_slice(&self.data);
+            bytes
+        }
     }
 
     #[derive(Serialize, Debug, Default)]
@@ -222,6 +276,26 @@ pub mod structs {
                 None
             }
         }
+
+        pub fn new(index: u32, offset: u32, plen: u32) -> Self {
+            Request {
+                head: Header {
+                    len: 13,
+                    byte: REQUEST,
+                },
+                index,
+                offset,
+                plen,
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.extend_from_slice(&self.index.to_be_bytes());
+            bytes.extend_from_slice(&self.offset.to_be_bytes());
+            bytes.extend_from_slice(&self.plen.to_be_bytes());
+            bytes
+        }
     }
     #[derive(Debug, Default, Clone)]
     pub struct Piece {
@@ -249,15 +323,7 @@ pub mod structs {
             }
             piece.index = parse_u32(&msg[5..9]);
             piece.offset = parse_u32(&msg[9..13]);
-            unsafe {
-                piece.data = Vec::new();
-                piece.data.reserve((piece.head.len - 9) as usize);
-                piece.data.set_len((piece.head.len - 9) as usize);
-                let src = msg.as_ptr().add(13);
-                let dst = piece.data.as_mut_ptr();
-                std::ptr::copy_nonoverlapping(src, dst, (piece.head.len - 9) as usize);
-                piece.data.set_len((piece.head.len - 9) as usize);
-            }
+            piece.data = msg[13..(piece.head.len + 4) as usize].to_vec();
 
             if piece.test() {
                 let x = msg.len() - (piece.head.len + 4) as usize;
@@ -322,6 +388,182 @@ pub mod structs {
                 None
             }
         }
+
+        pub fn new(req: &Request) -> Self {
+            Cancel {
+                head: Header {
+                    len: 13,
+                    byte: CANCEL,
+                },
+                index: req.index,
+                offset: req.offset,
+                plen: req.plen,
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.extend_from_slice(&self.index.to_be_bytes());
+            bytes.extend_from_slice(&self.offset.to_be_bytes());
+            bytes.extend_from_slice(&self.plen.to_be_bytes());
+            bytes
+        }
+    }
+
+    // bep 6 suggest piece and allowed fast, a single piece index like have
+    #[derive(Serialize, Debug, Default)]
+    pub struct PieceIndex {
+        pub head: Header,
+        pub index: u32,
+    }
+
+    impl PieceIndex {
+        fn test(&self) -> bool {
+            self.head.len == 5 && (self.head.byte == SUGGEST || self.head.byte == ALLOWED_FAST)
+        }
+
+        pub fn new(byte: u8, index: u32) -> Self {
+            PieceIndex {
+                head: Header { len: 5, byte },
+                index,
+            }
+        }
+
+        pub fn parse(msg: &mut Vec<u8>) -> Option<Self> {
+            if msg.len() < 9 {
+                return None;
+            }
+            let p = PieceIndex {
+                head: Header::parse(msg)?,
+                index: parse_u32(&msg[5..9]),
+            };
+
+            if p.test() {
+                msg.drain(0..9);
+                Some(p)
+            } else {
+                None
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.extend_from_slice(&self.index.to_be_bytes());
+            bytes
+        }
+    }
+
+    // bep 6 reject request, echoes the request that won't be answered
+    #[derive(Serialize, Debug, Default)]
+    pub struct Reject {
+        pub head: Header,
+        pub index: u32,
+        pub offset: u32,
+        pub plen: u32,
+    }
+
+    impl Reject {
+        fn test(&self) -> bool {
+            if self.head.byte != REJECT {
+                return false;
+            }
+            self.head.len == 13
+        }
+
+        pub fn new(req: &Request) -> Self {
+            Reject {
+                head: Header {
+                    len: 13,
+                    byte: REJECT,
+                },
+                index: req.index,
+                offset: req.offset,
+                plen: req.plen,
+            }
+        }
+
+        pub fn parse(msg: &mut Vec<u8>) -> Option<Self> {
+            if msg.len() < 17 {
+                return None;
+            }
+            let reject = Reject {
+                head: Header::parse(&msg[0..5])?,
+                index: parse_u32(&msg[5..9]),
+                offset: parse_u32(&msg[9..13]),
+                plen: parse_u32(&msg[13..17]),
+            };
+
+            if reject.test() {
+                msg.drain(0..17);
+                Some(reject)
+            } else {
+                None
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.extend_from_slice(&self.index.to_be_bytes());
+            bytes.extend_from_slice(&self.offset.to_be_bytes());
+            bytes.extend_from_slice(&self.plen.to_be_bytes());
+            bytes
+        }
+    }
+
+    // bep 10 message, ext_id 0 is the extended handshake and any other
+    // id is one the receiver assigned to an extension in its handshake
+    #[derive(Debug, Default, Clone)]
+    pub struct Extended {
+        pub head: Header,
+        pub ext_id: u8,
+        pub payload: Vec<u8>,
+    }
+
+    impl Extended {
+        fn test(&self) -> bool {
+            if self.head.byte != EXTENDED {
+                return false;
+            }
+            self.head.len as usize == (self.payload.len() + 2)
+        }
+
+        pub fn new(ext_id: u8, payload: Vec<u8>) -> Self {
+            Extended {
+                head: Header {
+                    len: payload.len() as u32 + 2,
+                    byte: EXTENDED,
+                },
+                ext_id,
+                payload,
+            }
+        }
+
+        pub fn parse(msg: &mut Vec<u8>) -> Option<Self> {
+            let head = Header::parse(msg)?;
+            let end = (head.len + 4) as usize;
+            if head.len < 2 || msg.len() < end {
+                return None;
+            }
+            let ext = Extended {
+                ext_id: msg[5],
+                payload: msg[6..end].to_vec(),
+                head,
+            };
+
+            if ext.test() {
+                msg.drain(0..end);
+                Some(ext)
+            } else {
+                None
+            }
+        }
+
+        pub fn as_bytes(&self) -> Vec<u8> {
+            let mut bytes = self.head.as_bytes();
+            bytes.push(self.ext_id);
+            bytes.extend_from_slice(&self.payload);
+            bytes
+        }
     }
 }
 
@@ -340,6 +582,12 @@ pub enum Message {
     Request(Request),
     Piece(Piece),
     Cancel(Cancel),
+    Suggest(PieceIndex),
+    HaveAll(Header),
+    HaveNone(Header),
+    Reject(Reject),
+    AllowedFast(PieceIndex),
+    Extended(Extended),
 }
 
 impl std::fmt::Debug for Message {
@@ -355,6 +603,12 @@ impl std::fmt::Debug for Message {
             Message::Request(_) => write!(f, "Request"),
             Message::Piece(_) => write!(f, "Piece"),
             Message::Cancel(_) => write!(f, "Cancel"),
+            Message::Suggest(_) => write!(f, "Suggest"),
+            Message::HaveAll(_) => write!(f, "HaveAll"),
+            Message::HaveNone(_) => write!(f, "HaveNone"),
+            Message::Reject(_) => write!(f, "Reject"),
+            Message::AllowedFast(_) => write!(f, "AllowedFast"),
+            Message::Extended(_) => write!(f, "Extended"),
         }
     }
 }
@@ -378,7 +632,7 @@ pub fn parse_msg(msg: &'static mut Vec<u8>) -> Vec<Message> {
         if is_zero(msg) {
             break;
         }
-        let _byte = match msg.get(0) {
+        let _byte = match msg.first() {
             Some(byte) => *byte,
             None => break,
         };
@@ -396,6 +650,12 @@ pub fn parse_msg(msg: &'static mut Vec<u8>) -> Vec<Message> {
             REQUEST => list.push(Message::Request(Request::parse(msg).unwrap())),
             PIECE => list.push(Message::Piece(Piece::parse(msg).unwrap())),
             CANCEL => list.push(Message::Cancel(Cancel::parse(msg).unwrap())),
+            SUGGEST => list.push(Message::Suggest(PieceIndex::parse(msg).unwrap())),
+            HAVE_ALL => list.push(Message::HaveAll(Header::parse(msg).unwrap())),
+            HAVE_NONE => list.push(Message::HaveNone(Header::parse(msg).unwrap())),
+            REJECT => list.push(Message::Reject(Reject::parse(msg).unwrap())),
+            ALLOWED_FAST => list.push(Message::AllowedFast(PieceIndex::parse(msg).unwrap())),
+            EXTENDED => list.push(Message::Extended(Extended::parse(msg).unwrap())),
             HANDSHAKE => list.push(Message::Handshake(Handshake::parse(msg).unwrap())),
             _ => {
                 // println!("{:?}", msg);
@@ -417,7 +677,7 @@ pub fn try_parse(original: &[u8]) -> bool {
         if is_zero(&msg) {
             return false;
         }
-        let _byte = match msg.get(0) {
+        let _byte = match msg.first() {
             Some(byte) => *byte,
             None => return true,
         };
@@ -426,7 +686,7 @@ pub fn try_parse(original: &[u8]) -> bool {
             None => return false,
         };
         match byte {
-            CHOKE | UNCHOKE | INTEREST | UNINTEREST => {
+            CHOKE | UNCHOKE | INTEREST | UNINTEREST | HAVE_ALL | HAVE_NONE => {
                 if Header::parse(&msg).is_none() {
                     return false;
                 }
@@ -456,6 +716,21 @@ pub fn try_parse(original: &[u8]) -> bool {
                     return false;
                 }
             }
+            SUGGEST | ALLOWED_FAST => {
+                if PieceIndex::parse(&mut msg).is_none() {
+                    return false;
+                }
+            }
+            REJECT => {
+                if Reject::parse(&mut msg).is_none() {
+                    return false;
+                }
+            }
+            EXTENDED => {
+                if Extended::parse(&mut msg).is_none() {
+                    return false;
+                }
+            }
             HANDSHAKE => {
                 if Handshake::parse(&mut msg).is_none() {
                     return false;
@@ -472,10 +747,10 @@ pub fn partial_parse(msg: &mut Vec<u8>) -> (bool, Vec<Message>) {
         return (false, list);
     }
     loop {
-        if is_zero(&msg) {
+        if is_zero(msg) {
             return (false, list);
         }
-        let _byte = match msg.get(0) {
+        let _byte = match msg.first() {
             Some(byte) => *byte,
             None => return (true, list),
         };
@@ -532,11 +807,48 @@ pub fn partial_parse(msg: &mut Vec<u8>) -> (bool, Vec<Message>) {
                 Some(x) => list.push(Message::Cancel(x)),
                 None => return (false, list),
             },
+            SUGGEST => match PieceIndex::parse(msg) {
+                Some(x) => list.push(Message::Suggest(x)),
+                None => return (false, list),
+            },
+            HAVE_ALL => match Header::parse(msg) {
+                Some(x) => {
+                    msg.drain(0..5);
+                    list.push(Message::HaveAll(x));
+                }
+                None => return (false, list),
+            },
+            HAVE_NONE => match Header::parse(msg) {
+                Some(x) => {
+                    msg.drain(0..5);
+                    list.push(Message::HaveNone(x));
+                }
+                None => return (false, list),
+            },
+            REJECT => match Reject::parse(msg) {
+                Some(x) => list.push(Message::Reject(x)),
+                None => return (false, list),
+            },
+            ALLOWED_FAST => match PieceIndex::parse(msg) {
+                Some(x) => list.push(Message::AllowedFast(x)),
+                None => return (false, list),
+            },
             HANDSHAKE => match Handshake::parse(msg) {
                 Some(x) => list.push(Message::Handshake(x)),
                 None => return (false, list),
             },
-            _ => return (false, list),
+            EXTENDED => match Extended::parse(msg) {
+                Some(x) => list.push(Message::Extended(x)),
+                None => return (false, list),
+            },
+            _ => {
+                // unknown messages are skipped whole
+                let end = parse_u32(msg) as usize + 4;
+                if msg.len() < end {
+                    return (false, list);
+                }
+                msg.drain(0..end);
+            }
         }
     }
 }
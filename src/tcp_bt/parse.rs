@@ -2,16 +2,12 @@
 
 use super::msg::structs::*;
 
-use crate::{
-    field::{constant::*, ByteField},
-    hash::Hasher,
-    tcp_bt::msg::{partial_parse, Message, SUBPIECE_LEN},
-};
+use crate::tcp_bt::msg::{partial_parse, Message};
 
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+        Arc,
     },
     thread::JoinHandle,
     vec,
@@ -23,9 +19,17 @@ use async_channel::{self, Receiver, Sender};
 
 pub struct ParseItem {
     pub rx: Receiver<Vec<u8>>,
-    pub tx: Sender<Request>,
+    // requests and cancels for the connection's seeder
+    pub tx: Sender<Message>,
+    // extended messages for the connection's extension handler
+    pub ext: Sender<Extended>,
+    // piece, interest and fast extension messages for the connection's state
+    // handler, chokes too when seeding
+    pub state: Sender<Message>,
     pub handle: task::JoinHandle<Option<()>>,
-    pub field: Option<Arc<Mutex<ByteField>>>,
+    // piece, reject and choke messages for the connection's fetcher, none
+    // when seeding
+    pub blocks: Option<Sender<Message>>,
 }
 pub struct Parser {
     pub tx: Sender<ParseItem>,
@@ -44,16 +48,10 @@ impl Parser {
     }
 }
 
-pub fn spawn_parsers(
-    parser: &Arc<Parser>,
-    hasher: &Arc<Hasher>,
-    handle: Handle,
-    threads: usize,
-) -> Vec<JoinHandle<()>> {
+pub fn spawn_parsers(parser: &Arc<Parser>, handle: Handle, threads: usize) -> Vec<JoinHandle<()>> {
     let mut handles = vec![];
     for i in 0..threads {
         let parser = Arc::clone(parser);
-        let hasher = Arc::clone(hasher);
         let handle = handle.clone();
         let builder = std::thread::Builder::new().name(format!("Parser{}", i));
         handles.push(
@@ -65,48 +63,46 @@ pub fn spawn_parsers(
 
                     let item = match handle.block_on(parser.rx.recv()) {
                         Ok(i) => i,
-                        Err(_) => break
+                        Err(_) => break,
                     };
 
                     let mut extbuf = vec![];
-                    let mut pieces = vec![];
-                    'buf: loop {
-                        match handle.block_on(item.rx.recv()) {
-                            Ok(b) => unsafe {
-                                let x = extbuf.len();
-                                let y = b.len();
-                                extbuf.reserve(y);
-                                extbuf.set_len(x + y);
-                                let src = b.as_ptr();
-                                let d: *mut u8 = extbuf.as_mut_ptr();
-                                let dst = d.add(x);
-                                std::ptr::copy_nonoverlapping(src, dst, y);
-                            },
-                            Err(_) => break,
-                        }
+                    while let Ok(b) = handle.block_on(item.rx.recv()) {
+                        extbuf.extend_from_slice(&b);
 
                         let (_, parsed) = partial_parse(&mut extbuf);
                         for m in parsed {
                             match m {
-                                Message::Piece(piece) => {
-                                    if item.field.is_some() {
-                                        let field = item.field.as_ref().unwrap();
-                                        {
-                                            let mut f = field.lock().unwrap();
-                                            f.arr[(piece.offset / SUBPIECE_LEN) as usize] =
-                                                COMPLETE;
-                                            if f.is_full() {
-                                                pieces.push(piece);
-                                                break 'buf;
-                                            }
-                                        }
-                                        pieces.push(piece);
+                                m @ (Message::Piece(_) | Message::Reject(_)) => {
+                                    if let Some(blocks) = &item.blocks {
+                                        let _ = handle.block_on(blocks.send(m));
+                                    }
+                                }
+                                // the fetcher acts on them in order with the blocks
+                                m @ (Message::Choke(_) | Message::Unchoke(_)) => {
+                                    let _ = match &item.blocks {
+                                        Some(blocks) => handle.block_on(blocks.send(m)),
+                                        None => handle.block_on(item.state.send(m)),
+                                    };
+                                }
+                                m @ (Message::Request(_) | Message::Cancel(_)) => {
+                                    if handle.block_on(item.tx.send(m)).is_err() {
+                                        break;
                                     }
                                 }
-                                Message::Request(req) => match handle.block_on(item.tx.send(req)) {
-                                    Ok(_) => {}
-                                    Err(_) => break,
-                                },
+                                Message::Extended(ext) => {
+                                    let _ = handle.block_on(item.ext.send(ext));
+                                }
+                                m @ (Message::Interest(_)
+                                | Message::Uninterest(_)
+                                | Message::Bitfield(_)
+                                | Message::Have(_)
+                                | Message::HaveAll(_)
+                                | Message::HaveNone(_)
+                                | Message::Suggest(_)
+                                | Message::AllowedFast(_)) => {
+                                    let _ = handle.block_on(item.state.send(m));
+                                }
                                 _ => continue,
                             }
                         }
@@ -115,16 +111,6 @@ pub fn spawn_parsers(
                     item.handle.abort();
                     let _ = handle.block_on(item.handle);
                     item.rx.close();
-
-                    if pieces.is_empty() {
-                        continue;
-                    }
-                    {
-                        // critical section
-                        let mut q = hasher.queue.lock().unwrap();
-                        q.push_back(pieces);
-                        hasher.loops.notify_one();
-                    }
                 })
                 .unwrap(),
         );
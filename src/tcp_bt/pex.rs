@@ -0,0 +1,267 @@
+// peer exchange over the extension protocol, bep 11 ut_pex
+#![allow(dead_code)]
+
+use super::{
+    ext::{ExtPeer, Extension},
+    Connector,
+};
+
+use crate::bencode::{self, ByteBuf};
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_channel::Sender;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::Mutex as TokioMutex,
+    task::{self, JoinHandle},
+    time,
+};
+
+pub const NAME: &str = "ut_pex";
+// peers may send at most one message a minute
+pub const PEX_INTERVAL: Duration = Duration::from_secs(60);
+// slack for messages that arrive a little early
+const MIN_RECV_INTERVAL: Duration = Duration::from_secs(45);
+// most peers in each added or dropped list
+pub const MAX_PEERS: usize = 50;
+
+// added.f flags
+pub const FLAG_ENCRYPTION: u8 = 0x01;
+pub const FLAG_SEED: u8 = 0x02;
+pub const FLAG_UTP: u8 = 0x04;
+pub const FLAG_HOLEPUNCH: u8 = 0x08;
+pub const FLAG_REACHABLE: u8 = 0x10;
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct PexMsg {
+    added: Option<ByteBuf>,
+    #[serde(rename = "added.f")]
+    added_f: Option<ByteBuf>,
+    added6: Option<ByteBuf>,
+    #[serde(rename = "added6.f")]
+    added6_f: Option<ByteBuf>,
+    dropped: Option<ByteBuf>,
+    dropped6: Option<ByteBuf>,
+}
+
+// compact ip:port pairs, 6 bytes each for ipv4 and 18 for ipv6
+pub fn to_compact(peers: &[SocketAddr], v6: bool) -> Vec<u8> {
+    let mut out = vec![];
+    for peer in peers {
+        match (peer.ip(), v6) {
+            (IpAddr::V4(ip), false) => out.extend_from_slice(&ip.octets()),
+            (IpAddr::V6(ip), true) => out.extend_from_slice(&ip.octets()),
+            _ => continue,
+        }
+        out.extend_from_slice(&peer.port().to_be_bytes());
+    }
+    out
+}
+
+pub fn from_compact(bytes: &[u8], v6: bool) -> Vec<SocketAddr> {
+    let len = if v6 { 18 } else { 6 };
+    let mut peers = vec![];
+    for chunk in bytes.chunks_exact(len) {
+        let ip = if v6 {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&chunk[..16]);
+            IpAddr::from(Ipv6Addr::from(octets))
+        } else {
+            IpAddr::from(Ipv4Addr::new(chunk[0], chunk[1], chunk[2], chunk[3]))
+        };
+        let port = u16::from_be_bytes([chunk[len - 2], chunk[len - 1]]);
+        if port != 0 {
+            peers.push(SocketAddr::new(ip, port));
+        }
+    }
+    peers
+}
+
+// shared swarm view, fed by connections and read by every pex sender
+pub struct Pex {
+    // peers we hold outgoing connections to, with their flags
+    connected: Mutex<HashMap<SocketAddr, u8>>,
+    // when each peer's last message was accepted
+    received: Mutex<HashMap<SocketAddr, Instant>>,
+    // learned peers, drained by the announce loop
+    tx: Sender<SocketAddr>,
+}
+
+impl Pex {
+    pub fn new(tx: Sender<SocketAddr>) -> Self {
+        Self {
+            connected: Mutex::new(HashMap::new()),
+            received: Mutex::new(HashMap::new()),
+            tx,
+        }
+    }
+
+    // only outgoing connections are shared, incoming ones have no listen port.
+    // reaching the peer ourselves is all we know of it until it says what it has
+    pub fn connected(&self, addr: SocketAddr) {
+        let mut c = self.connected.lock().unwrap();
+        c.insert(addr, FLAG_REACHABLE);
+    }
+
+    // flags a shared peer as a seed once its pieces are known
+    pub fn seed(&self, addr: SocketAddr, seed: bool) {
+        if let Some(f) = self.connected.lock().unwrap().get_mut(&addr) {
+            if seed {
+                *f |= FLAG_SEED;
+            } else {
+                *f &= !FLAG_SEED;
+            }
+        }
+    }
+
+    // called for every connection, incoming ones only clear the rate limit
+    pub fn disconnected(&self, addr: SocketAddr) {
+        self.connected.lock().unwrap().remove(&addr);
+        self.received.lock().unwrap().remove(&addr);
+    }
+
+    pub fn peers(&self) -> HashMap<SocketAddr, u8> {
+        self.connected.lock().unwrap().clone()
+    }
+
+    // next message for a peer given what it was last sent, updating sent
+    fn diff(&self, peer: Option<SocketAddr>, sent: &mut HashSet<SocketAddr>) -> Option<Vec<u8>> {
+        let mut now = self.peers();
+        if let Some(p) = peer {
+            now.remove(&p);
+        }
+
+        let mut added: Vec<(SocketAddr, u8)> = now
+            .iter()
+            .filter(|(a, _)| !sent.contains(a))
+            .map(|(a, f)| (*a, *f))
+            .collect();
+        let mut dropped: Vec<SocketAddr> = sent
+            .iter()
+            .filter(|a| !now.contains_key(a))
+            .copied()
+            .collect();
+        added.truncate(MAX_PEERS);
+        dropped.truncate(MAX_PEERS);
+        if added.is_empty() && dropped.is_empty() {
+            return None;
+        }
+        for (a, _) in &added {
+            sent.insert(*a);
+        }
+        for a in &dropped {
+            sent.remove(a);
+        }
+
+        let (added6, added): (Vec<_>, Vec<_>) = added.into_iter().partition(|(a, _)| a.is_ipv6());
+        let addrs = |list: &[(SocketAddr, u8)]| list.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        let flags = |list: &[(SocketAddr, u8)]| list.iter().map(|(_, f)| *f).collect::<Vec<_>>();
+        let some = |bytes: Vec<u8>| {
+            if bytes.is_empty() {
+                None
+            } else {
+                Some(ByteBuf(bytes))
+            }
+        };
+        let msg = PexMsg {
+            added: some(to_compact(&addrs(&added), false)),
+            added_f: some(flags(&added)),
+            added6: some(to_compact(&addrs(&added6), true)),
+            added6_f: some(flags(&added6)),
+            dropped: some(to_compact(&dropped, false)),
+            dropped6: some(to_compact(&dropped, true)),
+        };
+
+        bencode::to_bytes(&msg).ok()
+    }
+}
+
+impl Extension for Pex {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn on_message(&self, peer: &ExtPeer, payload: &[u8]) -> Vec<Vec<u8>> {
+        // drop messages from peers sending too often
+        if let Some(addr) = peer.addr {
+            let mut r = self.received.lock().unwrap();
+            let now = Instant::now();
+            if let Some(last) = r.get(&addr) {
+                if now.duration_since(*last) < MIN_RECV_INTERVAL {
+                    return vec![];
+                }
+            }
+            r.insert(addr, now);
+        }
+
+        let msg: PexMsg = match bencode::from_bytes(payload) {
+            Ok(m) => m,
+            Err(_) => return vec![],
+        };
+        let mut added = vec![];
+        if let Some(a) = &msg.added {
+            added.extend(from_compact(a, false).into_iter().take(MAX_PEERS));
+        }
+        if let Some(a) = &msg.added6 {
+            added.extend(from_compact(a, true).into_iter().take(MAX_PEERS));
+        }
+        for addr in added {
+            let _ = self.tx.try_send(addr);
+        }
+
+        vec![]
+    }
+}
+
+// periodically tells a peer which peers joined and left since the last message
+pub fn spawn_pex_sender(
+    write: &Arc<TokioMutex<OwnedWriteHalf>>,
+    ext_peer: &Arc<TokioMutex<ExtPeer>>,
+    connector: &Arc<Connector>,
+) -> JoinHandle<()> {
+    let write = Arc::clone(write);
+    let ext_peer = Arc::clone(ext_peer);
+    let connector = Arc::clone(connector);
+
+    task::spawn(async move {
+        let mut sent = HashSet::new();
+        loop {
+            time::sleep(PEX_INTERVAL).await;
+            if connector.brk.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let msg = {
+                let p = ext_peer.lock().await;
+                if !p.supports(NAME) {
+                    continue;
+                }
+                match connector.pex.diff(p.addr, &mut sent) {
+                    Some(payload) => p.message(NAME, payload),
+                    None => None,
+                }
+            };
+            let msg = match msg {
+                Some(m) => m,
+                None => continue,
+            };
+
+            let w;
+            {
+                let mut strm = write.lock().await;
+                w = strm.write_all(&msg).await;
+            }
+            if w.is_err() {
+                return;
+            }
+        }
+    })
+}
@@ -0,0 +1,132 @@
+// piece picking strategies, which piece a peer is asked for next
+#![allow(dead_code)]
+
+use super::state::{Availability, PeerPieces};
+
+use crate::field::{constant::*, ByteField};
+
+use std::collections::HashSet;
+
+use rand::seq::SliceRandom;
+
+pub trait PiecePicker: Send + Sync {
+    // an empty piece the peer has, none if it has nothing we need. partial
+    // pieces were started on another connection and have blocks kept, every
+    // picker takes one of those first so they get finished. refused pieces
+    // the peer rejected requests for are never picked for it
+    fn pick(
+        &self,
+        field: &ByteField,
+        peer: &PeerPieces,
+        avail: &Availability,
+        partial: &[usize],
+        refused: &HashSet<u32>,
+    ) -> Option<usize>;
+}
+
+// picker for a --picker flag value
+pub fn from_name(name: &str) -> Option<Box<dyn PiecePicker>> {
+    match name {
+        "rarest" => Some(Box::new(RarestFirst)),
+        "sequential" => Some(Box::new(Sequential)),
+        "random" => Some(Box::new(Random)),
+        _ => None,
+    }
+}
+
+fn wanted(field: &ByteField, peer: &PeerPieces, refused: &HashSet<u32>, index: usize) -> bool {
+    field.arr.get(index) == Some(&EMPTY) && peer.has(index) && !refused.contains(&(index as u32))
+}
+
+// pieces the fewest connected peers have first, so they don't disappear
+// with a peer, the rarest partial piece before all others
+pub struct RarestFirst;
+
+impl PiecePicker for RarestFirst {
+    fn pick(
+        &self,
+        field: &ByteField,
+        peer: &PeerPieces,
+        avail: &Availability,
+        partial: &[usize],
+        refused: &HashSet<u32>,
+    ) -> Option<usize> {
+        let partial = partial
+            .iter()
+            .copied()
+            .filter(|i| wanted(field, peer, refused, *i))
+            .min_by_key(|i| avail.get(*i));
+        if partial.is_some() {
+            return partial;
+        }
+
+        // random among the rarest so peers don't all start on the same one
+        let mut rarest = vec![];
+        let mut min = u32::MAX;
+        for i in (0..field.arr.len()).filter(|i| wanted(field, peer, refused, *i)) {
+            let count = avail.get(i);
+            if count < min {
+                min = count;
+                rarest.clear();
+            }
+            if count == min {
+                rarest.push(i);
+            }
+        }
+        rarest.choose(&mut rand::thread_rng()).copied()
+    }
+}
+
+// lowest index first, for playing media while it downloads, the lowest
+// partial piece before all others
+pub struct Sequential;
+
+impl PiecePicker for Sequential {
+    fn pick(
+        &self,
+        field: &ByteField,
+        peer: &PeerPieces,
+        _avail: &Availability,
+        partial: &[usize],
+        refused: &HashSet<u32>,
+    ) -> Option<usize> {
+        let partial = partial
+            .iter()
+            .copied()
+            .filter(|i| wanted(field, peer, refused, *i))
+            .min();
+        if partial.is_some() {
+            return partial;
+        }
+
+        (0..field.arr.len()).find(|i| wanted(field, peer, refused, *i))
+    }
+}
+
+// any piece the peer has, any partial piece before all others
+pub struct Random;
+
+impl PiecePicker for Random {
+    fn pick(
+        &self,
+        field: &ByteField,
+        peer: &PeerPieces,
+        _avail: &Availability,
+        partial: &[usize],
+        refused: &HashSet<u32>,
+    ) -> Option<usize> {
+        let partial: Vec<usize> = partial
+            .iter()
+            .copied()
+            .filter(|i| wanted(field, peer, refused, *i))
+            .collect();
+        if let Some(p) = partial.choose(&mut rand::thread_rng()) {
+            return Some(*p);
+        }
+
+        let pieces: Vec<usize> = (0..field.arr.len())
+            .filter(|i| wanted(field, peer, refused, *i))
+            .collect();
+        pieces.choose(&mut rand::thread_rng()).copied()
+    }
+}
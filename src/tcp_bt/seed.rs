@@ -1,6 +1,16 @@
 #![allow(dead_code)]
 
-use super::{connect::spawn_connector_task, msg::structs::Request, parse::Parser, Connector};
+use super::{
+    connect::spawn_connector_task,
+    ext::{spawn_ext_handler, ExtPeer},
+    msg::{
+        structs::{Reject, Request},
+        Message,
+    },
+    parse::Parser,
+    state::spawn_state_handler,
+    Connector,
+};
 
 use crate::{
     field::{constant::*, ByteField},
@@ -10,6 +20,7 @@ use crate::{
 };
 
 use std::{
+    collections::VecDeque,
     net::SocketAddr,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -27,7 +38,7 @@ use tokio::{
     task::{self, JoinHandle},
 };
 
-use async_channel;
+use async_channel::{self, Receiver};
 
 pub enum Peer {
     Addr(SocketAddr),
@@ -47,7 +58,7 @@ pub async fn spawn_listener(
     let torrent = Arc::clone(torrent);
     let field = Arc::clone(field);
     let count = Arc::clone(count);
-    return task::spawn(async move {
+    task::spawn(async move {
         let mut handles = vec![];
         loop {
             match listener.accept().await {
@@ -77,24 +88,34 @@ pub async fn spawn_listener(
         for t in handles {
             t.await.unwrap();
         }
-    });
+    })
 }
 
+// sends the requested subpiece, with the fast extension requests for
+// pieces we don't have are rejected rather than ending the connection
 pub async fn fulfill_req(
     write: &Arc<TokioMutex<OwnedWriteHalf>>,
     torrent: &Arc<Torrent>,
     field: &Arc<Mutex<ByteField>>,
     count: &Arc<AtomicU32>,
     req: &Request,
+    fast: bool,
 ) -> Option<()> {
-    task::block_in_place(|| {
+    let have = task::block_in_place(|| {
         let f = field.lock().unwrap();
-        if f.arr[req.index as usize] != COMPLETE {
+        f.arr.get(req.index as usize) == Some(&COMPLETE)
+    });
+    if !have {
+        if !fast {
             return None;
-        } else {
-            return Some(());
         }
-    })?;
+        let w;
+        {
+            let mut strm = write.lock().await;
+            w = strm.write_all(&Reject::new(req).as_bytes()).await;
+        }
+        return w.ok();
+    }
 
     let index = req.index as usize;
     let offset = req.offset as usize;
@@ -116,6 +137,72 @@ pub async fn fulfill_req(
     Some(())
 }
 
+fn queue_msg(queue: &mut VecDeque<Request>, msg: Message) {
+    match msg {
+        Message::Request(req) => queue.push_back(req),
+        Message::Cancel(c) => {
+            queue.retain(|r| !(r.index == c.index && r.offset == c.offset && r.plen == c.plen))
+        }
+        _ => {}
+    }
+}
+
+// serves the peer's requests in order, a cancel drops a matching one that
+// is still queued
+pub async fn serve_requests(
+    rx: Receiver<Message>,
+    write: &Arc<TokioMutex<OwnedWriteHalf>>,
+    torrent: &Arc<Torrent>,
+    field: &Arc<Mutex<ByteField>>,
+    count: &Arc<AtomicU32>,
+    peer: &Arc<TokioMutex<ExtPeer>>,
+) {
+    let mut queue = VecDeque::new();
+    loop {
+        if queue.is_empty() {
+            match rx.recv().await {
+                Ok(m) => queue_msg(&mut queue, m),
+                Err(_) => return,
+            }
+        }
+        // whatever else arrived meanwhile, cancels included
+        while let Ok(m) = rx.try_recv() {
+            queue_msg(&mut queue, m);
+        }
+        let req = match queue.pop_front() {
+            Some(r) => r,
+            None => continue,
+        };
+        let (fast, unchoked) = {
+            let p = peer.lock().await;
+            let unchoked = !p.choke.am_choking || p.allowed_fast.contains(&req.index);
+            (p.fast, unchoked)
+        };
+        // requests while we choke the peer are dropped, the fast extension
+        // rejects them instead
+        if !unchoked {
+            if fast
+                && write
+                    .lock()
+                    .await
+                    .write_all(&Reject::new(&req).as_bytes())
+                    .await
+                    .is_err()
+            {
+                return;
+            }
+            continue;
+        }
+        if fulfill_req(write, torrent, field, count, &req, fast)
+            .await
+            .is_none()
+        {
+            return;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn torrent_seeder(
     read: &Arc<TokioMutex<OwnedReadHalf>>,
     write: &Arc<TokioMutex<OwnedWriteHalf>>,
@@ -124,12 +211,18 @@ pub async fn torrent_seeder(
     field: &Arc<Mutex<ByteField>>,
     connector: &Arc<Connector>,
     count: &Arc<AtomicU32>,
+    ext_peer: &Arc<TokioMutex<ExtPeer>>,
 ) {
     let (byte_tx, byte_rx) = async_channel::unbounded();
     let (req_tx, req_rx) = async_channel::unbounded();
+    let (ext_tx, ext_rx) = async_channel::unbounded();
+    spawn_ext_handler(ext_rx, write, torrent, ext_peer);
+    let (state_tx, state_rx) = async_channel::unbounded();
+    spawn_state_handler(state_rx, write, torrent, field, connector, ext_peer);
 
     let read = Arc::clone(read);
     let write = Arc::clone(write);
+    let ext_peer = Arc::clone(ext_peer);
 
     let torrent = Arc::clone(torrent);
     let field = Arc::clone(field);
@@ -155,35 +248,25 @@ pub async fn torrent_seeder(
             byte_tx.send(buf[..bytes].to_vec()).await.unwrap();
         }
         drop(byte_tx);
-        return Some(());
+        Some(())
     });
 
     let item = ParseItem {
         rx: byte_rx,
         tx: req_tx,
+        ext: ext_tx,
+        state: state_tx,
         handle: reader,
-        field: None,
+        blocks: None,
     };
     if parser.tx.send(item).await.is_err() {
         return;
     }
 
     let seeder = task::spawn(async move {
-        loop {
-            let req = match req_rx.recv().await {
-                Ok(r) => r,
-                Err(_) => return,
-            };
-
-            match fulfill_req(&write, &torrent, &field, &count, &req).await {
-                Some(_) => {}
-                None => return,
-            }
-        }
+        serve_requests(req_rx, &write, &torrent, &field, &count, &ext_peer).await;
     });
 
     // reader.await.unwrap();
     seeder.await.unwrap();
-
-    return;
 }
@@ -0,0 +1,325 @@
+// which pieces each peer has, how many peers have each piece and the
+// choke and interest state of each connection
+#![allow(dead_code)]
+
+use super::{
+    ext::ExtPeer,
+    msg::{bytes::*, structs::Header, Message},
+    Connector,
+};
+
+use crate::{
+    field::{constant::*, ByteField},
+    torrent::Torrent,
+};
+
+use std::sync::{Arc, Mutex};
+
+use async_channel::Receiver;
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::Mutex as TokioMutex,
+    task::{self, JoinHandle},
+};
+
+// suggestions remembered per peer
+const MAX_SUGGESTED: usize = 32;
+
+// a peer's pieces from its bitfield, have all or have none and later haves
+#[derive(Default, Clone, Debug)]
+pub struct PeerPieces {
+    has: Vec<bool>,
+    count: usize,
+    // whether the peer said what it has yet
+    pub known: bool,
+}
+
+impl PeerPieces {
+    pub fn new(num_pieces: usize) -> Self {
+        Self {
+            has: vec![false; num_pieces],
+            count: 0,
+            known: false,
+        }
+    }
+
+    pub fn all(num_pieces: usize) -> Self {
+        Self {
+            has: vec![true; num_pieces],
+            count: num_pieces,
+            known: true,
+        }
+    }
+
+    // from a bitfield payload, none if it is the wrong length or sets spare bits
+    pub fn from_bitfield(bits: &[u8], num_pieces: usize) -> Option<Self> {
+        if bits.len() != num_pieces.div_ceil(8) {
+            return None;
+        }
+        let mut pieces = Self::new(num_pieces);
+        pieces.known = true;
+        for i in 0..bits.len() * 8 {
+            if bits[i / 8] & (0x80 >> (i % 8)) == 0 {
+                continue;
+            }
+            if i >= num_pieces {
+                return None;
+            }
+            pieces.has[i] = true;
+            pieces.count += 1;
+        }
+        Some(pieces)
+    }
+
+    pub fn len(&self) -> usize {
+        self.has.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_seed(&self) -> bool {
+        !self.has.is_empty() && self.count == self.has.len()
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        self.has.get(index) == Some(&true)
+    }
+
+    // records a have, returns whether the piece is new for this peer
+    pub fn have(&mut self, index: usize) -> bool {
+        self.known = true;
+        match self.has.get_mut(index) {
+            Some(h) if !*h => {
+                *h = true;
+                self.count += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// the four flags of a connection, both ends start out choking and not
+// interested
+#[derive(Clone, Copy, Debug)]
+pub struct Choke {
+    pub am_choking: bool,
+    pub am_interested: bool,
+    pub peer_choking: bool,
+    pub peer_interested: bool,
+}
+
+impl Default for Choke {
+    fn default() -> Self {
+        Self {
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+        }
+    }
+}
+
+// applies a choke, unchoke, interested or not interested from the peer,
+// returns what to send back. interested peers are unchoked for now, which
+// of them to choke is left to a choking algorithm
+pub fn choke_msg(p: &mut ExtPeer, msg: &Message) -> Vec<u8> {
+    match msg {
+        Message::Choke(_) => p.choke.peer_choking = true,
+        Message::Unchoke(_) => p.choke.peer_choking = false,
+        Message::Interest(_) => {
+            p.choke.peer_interested = true;
+            if p.choke.am_choking {
+                p.choke.am_choking = false;
+                return Header::new(UNCHOKE).as_bytes();
+            }
+        }
+        Message::Uninterest(_) => {
+            p.choke.peer_interested = false;
+            if !p.choke.am_choking {
+                p.choke.am_choking = true;
+                return Header::new(CHOKE).as_bytes();
+            }
+        }
+        _ => {}
+    }
+    vec![]
+}
+
+// whether the peer has a piece we still need, sends interested or not
+// interested when that changed
+pub fn update_interest(p: &mut ExtPeer, field: &Arc<Mutex<ByteField>>) -> Vec<u8> {
+    let interested = task::block_in_place(|| {
+        let f = field.lock().unwrap();
+        (0..f.arr.len()).any(|i| f.arr[i] != COMPLETE && p.pieces.has(i))
+    });
+    if interested == p.choke.am_interested {
+        return vec![];
+    }
+    p.choke.am_interested = interested;
+    if interested {
+        Header::new(INTEREST).as_bytes()
+    } else {
+        Header::new(UNINTEREST).as_bytes()
+    }
+}
+
+// swarm wide count of connected peers having each piece
+pub struct Availability {
+    counts: Vec<u32>,
+}
+
+impl Availability {
+    pub fn new(num_pieces: usize) -> Self {
+        Self {
+            counts: vec![0; num_pieces],
+        }
+    }
+
+    pub fn add(&mut self, pieces: &PeerPieces) {
+        for (c, h) in self.counts.iter_mut().zip(&pieces.has) {
+            if *h {
+                *c += 1;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, pieces: &PeerPieces) {
+        for (c, h) in self.counts.iter_mut().zip(&pieces.has) {
+            if *h {
+                *c = c.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn have(&mut self, index: usize) {
+        if let Some(c) = self.counts.get_mut(index) {
+            *c += 1;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        self.counts.get(index).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+}
+
+// swaps in what the peer now has, keeping the availability counts in step
+fn replace(p: &mut ExtPeer, avail: &Mutex<Availability>, pieces: PeerPieces) {
+    task::block_in_place(|| {
+        let mut a = avail.lock().unwrap();
+        a.remove(&p.pieces);
+        a.add(&pieces);
+    });
+    p.pieces = pieces;
+}
+
+// applies a message about the peer's pieces, false if it was invalid
+fn apply(p: &mut ExtPeer, avail: &Mutex<Availability>, num_pieces: usize, msg: Message) -> bool {
+    match msg {
+        Message::Bitfield(b) => match PeerPieces::from_bitfield(&b.data, num_pieces) {
+            Some(pieces) => replace(p, avail, pieces),
+            None => return false,
+        },
+        Message::Have(h) => {
+            let index = h.index as usize;
+            if index >= num_pieces {
+                return false;
+            }
+            if p.pieces.have(index) {
+                task::block_in_place(|| avail.lock().unwrap().have(index));
+            }
+        }
+        // the rest are fast extension messages
+        _ if !p.fast => {}
+        Message::HaveAll(_) => replace(p, avail, PeerPieces::all(num_pieces)),
+        Message::HaveNone(_) => {
+            let mut pieces = PeerPieces::new(num_pieces);
+            pieces.known = true;
+            replace(p, avail, pieces);
+        }
+        Message::Suggest(s) if (s.index as usize) < num_pieces => {
+            p.suggested.retain(|i| *i != s.index);
+            p.suggested.push(s.index);
+            if p.suggested.len() > MAX_SUGGESTED {
+                p.suggested.remove(0);
+            }
+        }
+        Message::AllowedFast(a) if (a.index as usize) < num_pieces => {
+            p.peer_allowed_fast.insert(a.index);
+        }
+        _ => {}
+    }
+    true
+}
+
+// stops counting a peer's pieces once its connection is over
+pub async fn disconnected(peer: &Arc<TokioMutex<ExtPeer>>, connector: &Arc<Connector>) {
+    let mut p = peer.lock().await;
+    p.closed = true;
+    let pieces = std::mem::take(&mut p.pieces);
+    task::block_in_place(|| connector.avail.lock().unwrap().remove(&pieces));
+}
+
+// records a connection's piece, choke and fast extension messages as the
+// parser forwards them, hanging up on peers sending invalid ones
+pub fn spawn_state_handler(
+    rx: Receiver<Message>,
+    write: &Arc<TokioMutex<OwnedWriteHalf>>,
+    torrent: &Arc<Torrent>,
+    field: &Arc<Mutex<ByteField>>,
+    connector: &Arc<Connector>,
+    peer: &Arc<TokioMutex<ExtPeer>>,
+) -> JoinHandle<()> {
+    let write = Arc::clone(write);
+    let torrent = Arc::clone(torrent);
+    let field = Arc::clone(field);
+    let connector = Arc::clone(connector);
+    let peer = Arc::clone(peer);
+
+    task::spawn(async move {
+        while let Ok(msg) = rx.recv().await {
+            let reply = {
+                let mut p = peer.lock().await;
+                if p.closed {
+                    return;
+                }
+                match msg {
+                    Message::Choke(_)
+                    | Message::Unchoke(_)
+                    | Message::Interest(_)
+                    | Message::Uninterest(_) => Some(choke_msg(&mut p, &msg)),
+                    // what the peer has decides whether we are interested
+                    _ if apply(&mut p, &connector.avail, torrent.num_pieces, msg) => {
+                        if let Some(addr) = p.addr {
+                            connector.pex.seed(addr, p.pieces.is_seed());
+                        }
+                        Some(update_interest(&mut p, &field))
+                    }
+                    _ => None,
+                }
+            };
+            let reply = match reply {
+                Some(r) if r.is_empty() => continue,
+                Some(r) => r,
+                None => {
+                    let _ = write.lock().await.shutdown().await;
+                    return;
+                }
+            };
+            if write.lock().await.write_all(&reply).await.is_err() {
+                return;
+            }
+        }
+    })
+}
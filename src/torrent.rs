@@ -1,44 +1,90 @@
 // holds all torrent metadata
-use std::sync::Arc;
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, sync::Arc};
 
 use crate::{
-    bencode::{decode::parse, Item},
+    dht::DhtConfig,
     file::{parse_file, FileSize},
     hash::split_hashes,
-    tracker::get_info_hash,
+    lsd::LsdConfig,
+    magnet::Magnet,
+    metainfo::{Metainfo, MetainfoError},
+    peer_id,
+    tcp_bt::{
+        ext::Registry,
+        picker::{PiecePicker, RarestFirst},
+    },
 };
 
+// default queue depth, enough blocks to fill a fast link's round trip
+pub const QUEUE_DEPTH: usize = 128;
+
 pub struct Torrent {
-    pub tree: Vec<Item>,
+    pub meta: Metainfo,
     pub info_hash: [u8; 20],
+    // ours, sent in every handshake and announce
+    pub peer_id: [u8; 20],
     pub files: Arc<Vec<FileSize>>,
     pub file_len: usize,
     pub piece_len: usize,
     pub num_pieces: usize,
     pub hashes: Vec<Vec<u8>>,
+    // peers known up front, e.g. a magnet's x.pe entries
+    pub peers: Vec<SocketAddr>,
+    // extension protocol handlers offered to every peer
+    pub extensions: Registry,
+    // dht node to find peers with, none for private torrents
+    pub dht: Option<DhtConfig>,
+    // local service discovery on the lan, none for private torrents
+    pub lsd: Option<LsdConfig>,
+    // peer exchange over ut_pex, off for private torrents
+    pub pex: bool,
+    // chooses which piece to request from a peer next
+    pub picker: Box<dyn PiecePicker>,
+    // most blocks requested from one peer at once, lowered to the peer's reqq
+    pub queue_depth: usize,
 }
 
 impl Torrent {
-    pub async fn new(bytes: &[u8]) -> Self {
-        let mut copy = bytes.to_vec();
-        let tree = parse(&mut copy);
-        let dict = tree[0].get_dict();
-        let info = dict.get("info".as_bytes()).unwrap().get_dict();
-        let piece_len = info.get("piece length".as_bytes()).unwrap().get_int();
-        let num_pieces = info.get("pieces".as_bytes()).unwrap().get_str().len() / 20;
-        let hashes = info.get("pieces".as_bytes()).unwrap().get_str();
-        let split_hashes = split_hashes(&hashes);
-
-        let (files, file_len) = parse_file(&info).await;
+    pub async fn new(bytes: &[u8]) -> Result<Self, MetainfoError> {
+        Ok(Self::from_meta(Metainfo::from_bytes(bytes)?).await)
+    }
+
+    pub async fn from_meta(meta: Metainfo) -> Self {
+        let info = &meta.info;
+        let piece_len = info.piece_length as usize;
+        let num_pieces = info.num_pieces();
+        let split_hashes = split_hashes(&info.pieces);
+
+        let (files, file_len) = parse_file(info).await;
+        // private torrents only get peers from their trackers, bep 27
+        let (dht, lsd, pex) = match info.private {
+            Some(true) => (None, None, false),
+            _ => (Some(DhtConfig::default()), Some(LsdConfig::default()), true),
+        };
 
         Self {
-            tree,
-            info_hash: get_info_hash(bytes.to_vec()),
+            info_hash: meta.info_hash,
+            peer_id: peer_id::generate(),
+            meta,
             files,
             file_len,
             piece_len,
             num_pieces,
             hashes: split_hashes,
+            peers: vec![],
+            extensions: Registry::new(),
+            dht,
+            lsd,
+            pex,
+            picker: Box::new(RarestFirst),
+            queue_depth: QUEUE_DEPTH,
         }
     }
+
+    // magnet link sharing this torrent's hash, name, trackers and web seeds
+    pub fn magnet(&self) -> Magnet {
+        Magnet::from_meta(&self.meta)
+    }
 }
@@ -3,7 +3,11 @@
 
 use super::IpPort;
 
-use crate::bencode::{decode::parse, Item};
+use crate::bencode::{
+    de::from_item,
+    stream::{Status, StreamDecoder},
+    ByteBuf,
+};
 
 use std::{
     io::{Error, ErrorKind},
@@ -11,15 +15,26 @@ use std::{
     str::from_utf8,
 };
 
+use serde::Deserialize;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
 };
 
+// bencoded body of a tracker's announce reply
+#[derive(Deserialize, Debug)]
+struct AnnounceResp {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<ByteBuf>,
+    interval: Option<i64>,
+    peers: Option<ByteBuf>,
+}
+
 // takes in info_hash and tracker addr, announces and gets peer IpPorts
 pub async fn http_announce(
     addr: SocketAddr,
     info_hash: [u8; 20],
+    peer_id: [u8; 20],
     port: u16,
 ) -> Result<Vec<IpPort>, Error> {
     let mut get: Vec<u8> = vec![];
@@ -29,62 +44,82 @@ pub async fn http_announce(
     for byte in &info_hash {
         base.push_str(&format!("%{:02x}", byte));
     }
+    base.push_str("&peer_id=");
+    for byte in &peer_id {
+        base.push_str(&format!("%{:02x}", byte));
+    }
     // append suffix of get request
-    base.push_str("&peer_id=-qB4250-rj6kZQu4P_Mh&port=");
+    base.push_str("&port=");
     base.push_str(&format!("{}", port));
     base.push_str("&uploaded=0&downloaded=0&left=1456927919\
     &corrupt=0&key=8B26698B&event=started&numwant=200&compact=1&no_peer_id=1&supportcrypto=1&redundant=0\
     HTTP/1.1\r\n\r\n");
     // convert base to Vec<u8> and append to get vector
-    get.extend_from_slice(&base.as_bytes());
+    get.extend_from_slice(base.as_bytes());
     // connect to the tracker
     let mut stream = TcpStream::connect(addr).await?;
     // send the get request to the tracker
     stream.write_all(&get).await?;
-    // read it's reply
-    let mut buf: Vec<u8> = vec![0; 10000];
-    let len;
-    match stream.read(&mut buf).await {
-        Ok(l) => len = l,
-        Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
-    }
-    buf.truncate(len);
-    // remove http header
-    let mut count = 0;
-    for i in buf.windows(4) {
-        count += 1;
-        if i.iter().zip(b"\r\n\r\n").all(|(a, b)| *a == *b) {
-            break;
-        };
+    // read it's reply up to the end of the http header
+    let mut buf: Vec<u8> = vec![0; 4096];
+    let mut head: Vec<u8> = vec![];
+    let mut body = loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "no http body"));
+        }
+        head.extend_from_slice(&buf[..len]);
+        // remove http header
+        if let Some(i) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            break head.split_off(i + 4);
+        }
+    };
+    while body.is_empty() {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "no http body"));
+        }
+        body.extend_from_slice(&buf[..len]);
     }
-    buf.drain(0..count + 4);
-    if buf[0] != b'd' {
-        buf.insert(0, b'd');
-        buf.push(b'e');
+    // some trackers leave out the outer dict
+    let wrap = body[0] != b'd';
+    let mut decoder = StreamDecoder::new();
+    if wrap {
+        decoder.feed(b"d");
     }
-    // parse out ip port and return
-    let tree: Vec<Item> = parse(&mut buf);
-    match &tree[0] {
-        Item::Dict(d) => {
-            if let Some(e) = d.get("failure reason".as_bytes()) {
-                match e {
-                    Item::String(s) => {
-                        return Err(Error::new(
-                            ErrorKind::Other,
-                            from_utf8(s).unwrap().to_string(),
-                        ));
-                    }
-                    _ => unreachable!(),
-                }
+    decoder.feed(&body);
+    // decode the body as it arrives
+    let mut eof = false;
+    let item = loop {
+        match decoder.decode() {
+            Ok(Status::Complete(item, _)) => break item,
+            Ok(Status::NeedMore) => {}
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+        }
+        if eof {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "reply ends early"));
+        }
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            eof = true;
+            if wrap {
+                decoder.feed(b"e");
             }
         }
-        _ => unreachable!(),
+        decoder.feed(&buf[..len]);
+    };
+    // parse out ip port and return
+    let resp: AnnounceResp = match from_item(item) {
+        Ok(r) => r,
+        Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+    };
+    if let Some(s) = resp.failure_reason {
+        return Err(Error::other(from_utf8(&s).unwrap_or("failure").to_string()));
     }
-    let peers = tree[0]
-        .get_dict()
-        .get("peers".as_bytes())
-        .unwrap()
-        .get_str();
+    let peers = match resp.peers {
+        Some(p) => p,
+        None => return Err(Error::new(ErrorKind::InvalidData, "no peers in reply")),
+    };
 
     Ok(IpPort::from_bytes(&peers))
 }
@@ -12,8 +12,9 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
-use crate::bencode::Item;
+use crate::metainfo::Metainfo;
 
 use self::{http::http_announce, udp::udp_announce};
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -26,7 +27,7 @@ impl IpPort {
     // takes in byte string of ip:port pairs and parses them
     fn from_bytes(bytes: &[u8]) -> Vec<Self> {
         let mut peers: Vec<IpPort> = vec![];
-        if bytes.len() % 6 != 0 {
+        if !bytes.len().is_multiple_of(6) {
             return peers;
         }
         for chunk in bytes.chunks(6) {
@@ -57,31 +58,30 @@ impl std::fmt::Debug for IpPort {
     }
 }
 
-// computes info_hash from .torrent bytes
-pub fn get_info_hash(mut bytes: Vec<u8>) -> [u8; 20] {
-    let mut len: usize = 0;
-    for c in bytes.windows(7) {
-        len += 1;
-        if c.eq(b"4:infod") {
-            break;
-        }
-    }
-    bytes.drain(0..len + 5);
-    bytes.pop();
-
+// computes info_hash from the raw bytes of the info dict
+pub fn get_info_hash(info: &[u8]) -> [u8; 20] {
     let mut hasher = Sha1::new();
-    hasher.update(bytes);
+    hasher.update(info);
 
     hasher.finalize().into()
 }
+
+// v2 info_hash, sha256 of the same bytes
+pub fn get_info_hash_v2(info: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(info);
+
+    hasher.finalize().into()
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Addr {
     Udp(SocketAddr),
     Http(SocketAddr),
 }
 
-fn make_addr(announce: &Item) -> Result<Addr, String> {
-    let mut url = announce.get_str();
+pub fn make_addr(announce: &str) -> Result<Addr, String> {
+    let mut url = announce.as_bytes().to_vec();
     // get url URI i.e udp://
     let mut count = 0;
     let mut len = 0;
@@ -96,12 +96,12 @@ fn make_addr(announce: &Item) -> Result<Addr, String> {
     }
     let mut addr;
     // handle each URI
-    let udp = url[0] == b'u';
+    let udp = url.first() == Some(&b'u');
     match &url[0..len] {
         b"http://" => url.drain(0.."http://".len()),
         b"udp://" => url.drain(0.."udp://".len()),
         b"https://" => return Err("HTTPS/TLS not supported".to_string()),
-        _ => return Err(format!("unknown URI: {}", from_utf8(&url).unwrap())),
+        _ => return Err(format!("unknown URI: {}", String::from_utf8_lossy(&url))),
     };
     // remove any /announce
     match url.iter().find(|i| **i == b'/') {
@@ -121,7 +121,11 @@ fn make_addr(announce: &Item) -> Result<Addr, String> {
         _ => addr.push_str(":80"),
     }
     // resolve socketaddr
-    match addr.to_socket_addrs().unwrap().next() {
+    let mut addrs = match addr.to_socket_addrs() {
+        Ok(a) => a,
+        Err(e) => return Err(format!("{} {:?}", e, addr)),
+    };
+    match addrs.next() {
         Some(s) => {
             if udp {
                 Ok(Addr::Udp(s))
@@ -133,30 +137,26 @@ fn make_addr(announce: &Item) -> Result<Addr, String> {
     }
 }
 
-pub fn get_addr(tree: &[Item]) -> Result<Addr, String> {
-    let dict = tree[0].get_dict();
-    match dict.get("announce".as_bytes()) {
-        Some(s) => match make_addr(&s) {
-            Ok(s) => Ok(s),
-            Err(e) => match dict.get("announce-list".as_bytes()) {
-                Some(l) => {
-                    for i in l.get_list() {
-                        if let Ok(s) = make_addr(&i.get_list()[0]) {
-                            return Ok(s);
-                        }
-                    }
-                    Err(e)
-                }
-                None => Err(e),
-            },
-        },
-        None => Err("no announce url found".to_string()),
+// first tracker url that resolves, trying announce then announce-list
+pub fn get_addr(meta: &Metainfo) -> Result<Addr, String> {
+    let mut err = "no announce url found".to_string();
+    for url in meta.trackers() {
+        match make_addr(url) {
+            Ok(s) => return Ok(s),
+            Err(e) => err = e,
+        }
     }
+    Err(err)
 }
 
-pub async fn announce(addr: Addr, info_hash: [u8; 20], port: u16) -> Result<Vec<IpPort>, Error> {
+pub async fn announce(
+    addr: Addr,
+    info_hash: [u8; 20],
+    peer_id: [u8; 20],
+    port: u16,
+) -> Result<Vec<IpPort>, Error> {
     match addr {
-        Addr::Http(a) => http_announce(a, info_hash, port).await,
-        Addr::Udp(a) => udp_announce(a, info_hash, port).await,
+        Addr::Http(a) => http_announce(a, info_hash, peer_id, port).await,
+        Addr::Udp(a) => udp_announce(a, info_hash, peer_id, port).await,
     }
 }
@@ -57,7 +57,7 @@ struct AnnounceResp {
 }
 
 impl AnnounceResp {
-    fn from_be(&mut self) -> &mut Self {
+    fn swap_be(&mut self) -> &mut Self {
         self.action = u32::from_be(self.action);
         self.transaction_id = u32::from_be(self.transaction_id);
         self.interval = u32::from_be(self.interval);
@@ -72,6 +72,7 @@ impl AnnounceResp {
 pub async fn udp_announce(
     addr: SocketAddr,
     info_hash: [u8; 20],
+    peer_id: [u8; 20],
     port: u16,
 ) -> Result<Vec<IpPort>, Error> {
     // set up udp socket
@@ -104,7 +105,7 @@ pub async fn udp_announce(
         action: u32::to_be(1),
         transaction_id: random::<u32>(),
         info_hash,
-        peer_id: [1; 20],
+        peer_id,
         downloaded: 0,
         left: 0,
         uploaded: 0,